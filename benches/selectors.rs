@@ -0,0 +1,107 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use xmlem::{Document, Element, Selector};
+
+/// A `values/strings.xml` of `count` strings, plurals and string arrays.
+fn values(count: usize) -> Document {
+    let mut doc = Document::new("resources");
+    let root = doc.root();
+    for i in 0..count {
+        let name = format!("res_{i}");
+        match i % 10 {
+            0 => {
+                let plurals = root.append_new_element(&mut doc, ("plurals", [("name", &name)]));
+                for quantity in ["zero", "one", "two", "few", "many", "other"] {
+                    let item =
+                        plurals.append_new_element(&mut doc, ("item", [("quantity", quantity)]));
+                    item.append_text(&mut doc, quantity);
+                }
+            }
+            1 => {
+                let array = root.append_new_element(&mut doc, ("string-array", [("name", &name)]));
+                for j in 0..5 {
+                    let item = array.append_new_element(&mut doc, ("item", [("n", j.to_string())]));
+                    item.append_text(&mut doc, "item");
+                }
+            }
+            _ => {
+                let string = root.append_new_element(&mut doc, ("string", [("name", &name)]));
+                string.append_text(&mut doc, "Mlem");
+            }
+        }
+    }
+    doc
+}
+
+/// A layout of 1,000 screens, each nesting linear layouts ten deep with text views at every
+/// level.
+fn layout() -> Document {
+    let mut doc = Document::new("FrameLayout");
+    let root = doc.root();
+    for i in 0..1_000 {
+        let mut parent =
+            root.append_new_element(&mut doc, ("merge", [("tools:screen", i.to_string())]));
+        for j in 0..10 {
+            parent = parent.append_new_element(
+                &mut doc,
+                ("LinearLayout", [("android:orientation", "vertical")]),
+            );
+            for k in 0..10 {
+                let id = format!("@+id/text_{i}_{j}_{k}");
+                parent.append_new_element(&mut doc, ("TextView", [("android:id", id)]));
+            }
+        }
+    }
+    doc
+}
+
+/// Matches every element separately, without the caches shared by a query.
+fn match_each(doc: &Document, selector: &Selector) -> usize {
+    doc.root()
+        .descendants(doc)
+        .filter(|e: &Element| selector.matches(doc, *e))
+        .count()
+}
+
+fn selectors(c: &mut Criterion) {
+    let large = values(50_000);
+    // Matching `:nth-child` element by element is quadratic, so compare on a smaller file.
+    let small = values(5_000);
+    let layout = layout();
+    let nth = Selector::new("string:nth-child(2n of [name$='7'])").unwrap();
+    let plurals = Selector::new("plurals > item[quantity=one]").unwrap();
+    let hit = Selector::new("merge LinearLayout LinearLayout > TextView").unwrap();
+    let miss = Selector::new("ScrollView TextView, RelativeLayout > TextView").unwrap();
+
+    let mut group = c.benchmark_group("android resources");
+    group.sample_size(10);
+
+    group.bench_function("query_selector_all nth-child of", |b| {
+        b.iter(|| black_box(large.query_selector_all(&nth).len()))
+    });
+    group.bench_function("query_selector_all nth-child of small", |b| {
+        b.iter(|| black_box(small.query_selector_all(&nth).len()))
+    });
+    group.bench_function("match each nth-child of small", |b| {
+        b.iter(|| black_box(match_each(&small, &nth)))
+    });
+    group.bench_function("query_selector_all child", |b| {
+        b.iter(|| black_box(large.query_selector_all(&plurals).len()))
+    });
+    group.bench_function("query_selector_all descendant hit", |b| {
+        b.iter(|| black_box(layout.query_selector_all(&hit).len()))
+    });
+    group.bench_function("match each descendant hit", |b| {
+        b.iter(|| black_box(match_each(&layout, &hit)))
+    });
+    group.bench_function("query_selector_all descendant miss", |b| {
+        b.iter(|| black_box(layout.query_selector_all(&miss).len()))
+    });
+    group.bench_function("match each descendant miss", |b| {
+        b.iter(|| black_box(match_each(&layout, &miss)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, selectors);
+criterion_main!(benches);
@@ -0,0 +1,79 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use xmlem::{Document, Element, Selector};
+
+const NODES: usize = 1_000_000;
+
+/// A root with a thousand children, each with a thousand children of their own.
+fn wide() -> Document {
+    let mut doc = Document::new("root");
+    let root = doc.root();
+    for i in 0..1_000 {
+        let group = root.append_new_element(&mut doc, ("group", [("n", i.to_string())]));
+        for j in 0..1_000 {
+            let class = if j % 100 == 0 { "hit" } else { "miss" };
+            group.append_new_element(&mut doc, ("item", [("class", class)]));
+        }
+    }
+    doc
+}
+
+/// A single chain of nested elements.
+fn deep() -> Document {
+    let mut doc = Document::new("root");
+    let mut parent = doc.root();
+    for _ in 1..NODES {
+        parent = parent.append_new_element(&mut doc, ("item", [("class", "x")]));
+    }
+    doc
+}
+
+fn next_siblings(doc: &Document, first: Element) -> usize {
+    let mut count = 0;
+    let mut current = Some(first);
+    while let Some(element) = current {
+        count += 1;
+        current = element.next_sibling_element(doc);
+    }
+    count
+}
+
+fn traversal(c: &mut Criterion) {
+    let wide = wide();
+    let deep = deep();
+    let hit = Selector::new("group > item.hit").unwrap();
+
+    let mut group = c.benchmark_group("1M nodes");
+    group.sample_size(10);
+
+    group.bench_function("walk wide", |b| {
+        b.iter(|| black_box(wide.root().walk(&wide).count()))
+    });
+    group.bench_function("walk deep", |b| {
+        b.iter(|| black_box(deep.root().walk(&deep).count()))
+    });
+    group.bench_function("traverse wide", |b| {
+        b.iter(|| black_box(wide.root().traverse(&wide).count()))
+    });
+    group.bench_function("next_sibling_element wide", |b| {
+        let groups = wide.root().children(&wide);
+        b.iter(|| {
+            let count: usize = groups
+                .iter()
+                .map(|g| next_siblings(&wide, g.child_elements(&wide).next().unwrap()))
+                .sum();
+            black_box(count)
+        })
+    });
+    group.bench_function("query_selector_all wide", |b| {
+        b.iter(|| black_box(wide.root().query_selector_all(&wide, &hit).len()))
+    });
+    group.bench_function("ancestors deep", |b| {
+        let leaf = deep.root().descendants(&deep).last().unwrap();
+        b.iter(|| black_box(leaf.ancestors(&deep).count()))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, traversal);
+criterion_main!(benches);
@@ -0,0 +1,196 @@
+use std::fmt::Display;
+
+use crate::{error::parse_name, Document, Element, Error, NewElement, Node, XmlChild};
+
+/// Builds an element and its content in plain Rust, for XML that is generated dynamically.
+///
+/// Nothing is created until the builder is appended to an element with
+/// [`append_to`](ElementBuilder::append_to) or turned into a document with
+/// [`into_document`](ElementBuilder::into_document). Invalid names are reported at that point.
+///
+/// ```
+/// use xmlem::{Document, ElementBuilder};
+///
+/// let keys = ["q", "w", "e"];
+/// let row = ElementBuilder::new("Row")
+///     .attr("x", 1)
+///     .comment("generated")
+///     .children(keys.iter().map(|k| ElementBuilder::new("key").attr("code", k)))
+///     .child(ElementBuilder::new("label").text("QWE"));
+///
+/// let mut doc = Document::new("keyboard");
+/// let root = doc.root();
+/// let nodes = row.append_to(&mut doc, root);
+/// assert_eq!(nodes.len(), 7);
+/// assert_eq!(
+///     doc.to_string(),
+///     r#"<keyboard><Row x="1"><!--generated--><key code="q"/><key code="w"/><key code="e"/><label>QWE</label></Row></keyboard>"#
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct ElementBuilder {
+    element: Result<NewElement, Error>,
+    content: Vec<Content>,
+}
+
+#[derive(Debug, Clone)]
+enum Content {
+    Element(ElementBuilder),
+    Text(String),
+    CData(String),
+    Comment(String),
+}
+
+impl ElementBuilder {
+    pub fn new(name: &str) -> Self {
+        ElementBuilder {
+            element: NewElement::try_new(name, [] as [(&str, &str); 0]),
+            content: vec![],
+        }
+    }
+
+    /// Set an attribute, replacing any previous value.
+    pub fn attr(mut self, name: &str, value: impl Display) -> Self {
+        if let Ok(element) = &mut self.element {
+            match parse_name(name) {
+                Ok(name) => {
+                    element.attrs.insert(name, value.to_string());
+                }
+                Err(e) => self.element = Err(e),
+            }
+        }
+        self
+    }
+
+    pub fn child(mut self, child: ElementBuilder) -> Self {
+        self.content.push(Content::Element(child));
+        self
+    }
+
+    pub fn children(mut self, children: impl IntoIterator<Item = ElementBuilder>) -> Self {
+        self.content
+            .extend(children.into_iter().map(Content::Element));
+        self
+    }
+
+    pub fn text(mut self, text: impl Display) -> Self {
+        self.content.push(Content::Text(text.to_string()));
+        self
+    }
+
+    pub fn cdata(mut self, text: impl Display) -> Self {
+        self.content.push(Content::CData(text.to_string()));
+        self
+    }
+
+    pub fn comment(mut self, text: impl Display) -> Self {
+        self.content.push(Content::Comment(text.to_string()));
+        self
+    }
+
+    /// Create the element and its content as the last child of `parent`.
+    ///
+    /// Returns handles to all created nodes in document order, starting with the element itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a name is invalid or `parent` is not an element of `document`. Use
+    /// [`ElementBuilder::try_append_to`] for a fallible alternative.
+    pub fn append_to(self, document: &mut Document, parent: Element) -> Vec<Node> {
+        match self.try_append_to(document, parent) {
+            Ok(nodes) => nodes,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub fn try_append_to(
+        self,
+        document: &mut Document,
+        parent: Element,
+    ) -> Result<Vec<Node>, Error> {
+        self.validate()?;
+        parent.try_value(document)?;
+        let mut nodes = vec![];
+        self.create(document, parent, &mut nodes);
+        Ok(nodes)
+    }
+
+    /// Create a new document with this element as its root.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a name is invalid. Use [`ElementBuilder::try_into_document`] for a fallible
+    /// alternative.
+    pub fn into_document(self) -> Document {
+        match self.try_into_document() {
+            Ok(document) => document,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub fn try_into_document(self) -> Result<Document, Error> {
+        self.validate()?;
+        let element = self.element.unwrap();
+        let mut document = Document::new("_");
+        let root = document.root();
+        root.set_name(&mut document, element.name);
+        document.attrs.insert(root.0, element.attrs);
+
+        let mut nodes = vec![];
+        for content in self.content {
+            content.create(&mut document, root, &mut nodes);
+        }
+        Ok(document)
+    }
+
+    /// Finds the first invalid name, so that nothing is created if there is one.
+    fn validate(&self) -> Result<(), Error> {
+        let mut stack = vec![self];
+        while let Some(builder) = stack.pop() {
+            if let Err(e) = &builder.element {
+                return Err(e.clone());
+            }
+            stack.extend(builder.content.iter().filter_map(|c| match c {
+                Content::Element(child) => Some(child),
+                _ => None,
+            }));
+        }
+        Ok(())
+    }
+
+    fn create(self, document: &mut Document, parent: Element, nodes: &mut Vec<Node>) {
+        let element = parent.append_new_element(document, self.element.unwrap());
+        nodes.push(element.as_node());
+        for content in self.content {
+            content.create(document, element, nodes);
+        }
+    }
+}
+
+impl Content {
+    fn create(self, document: &mut Document, parent: Element, nodes: &mut Vec<Node>) {
+        let node = match self {
+            Content::Element(builder) => return builder.create(document, parent, nodes),
+            Content::Text(text) => parent.append_text(document, &text).into(),
+            Content::CData(text) => parent.append_cdata(document, &text).into(),
+            Content::Comment(text) => parent.append_comment(document, &text).into(),
+        };
+        nodes.push(node);
+    }
+}
+
+impl From<NewElement> for ElementBuilder {
+    fn from(element: NewElement) -> Self {
+        ElementBuilder {
+            element: Ok(element),
+            content: vec![],
+        }
+    }
+}
+
+/// Builders can be used in the child lists of [`xml!`](crate::xml).
+impl XmlChild for ElementBuilder {
+    fn append_to(self, document: &mut Document, parent: Element) -> Node {
+        ElementBuilder::append_to(self, document, parent)[0]
+    }
+}
@@ -412,7 +412,8 @@ impl Print<Config, State<'_>> for NodeValue {
                 write!(f, "{:>indent$}", "", indent = context.indent)?;
             }
 
-            write!(f, "<![CDATA[{t}]]>")?;
+            // A CDATA section cannot contain its own terminator, so split it across two sections.
+            write!(f, "<![CDATA[{}]]>", t.replace("]]>", "]]]]><![CDATA[>"))?;
 
             if config.indent_text_nodes && context.is_pretty {
                 writeln!(f)?;
@@ -443,18 +444,13 @@ impl Print<Config, State<'_>> for NodeValue {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum EntityMode {
+    #[default]
     Standard,
     Hex,
 }
 
-impl Default for EntityMode {
-    fn default() -> Self {
-        Self::Standard
-    }
-}
-
 fn process_entities(
     input: &str,
     mode: EntityMode,
@@ -512,11 +508,9 @@ struct FmtWriter<'a, 'b>(&'b mut std::fmt::Formatter<'a>);
 
 impl Write for FmtWriter<'_, '_> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let s = std::str::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        self.0
-            .write_str(s)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        Ok(s.as_bytes().len())
+        let s = std::str::from_utf8(buf).map_err(io::Error::other)?;
+        self.0.write_str(s).map_err(io::Error::other)?;
+        Ok(s.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
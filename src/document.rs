@@ -1,31 +1,78 @@
 use std::{
     cmp::{min, Ordering},
-    error::Error,
-    fmt,
+    error, fmt,
     io::BufRead,
+    ops::{Deref, DerefMut},
     str::Utf8Error,
 };
 
 use indexmap::IndexMap;
 use once_cell::sync::Lazy;
 use qname::QName;
-use slotmap::{SlotMap, SparseSecondaryMap};
+
+use slotmap::{SecondaryMap, SlotMap, SparseSecondaryMap};
 
 use crate::{
     display::{self, Config, Print, State},
-    element::Element,
-    key::{CDataSection, Comment, DocKey, DocumentType, Text},
+    element::{Element, NewElement},
+    error::{parse_name, Error},
+    iter::QuerySelectorIter,
+    key::{CDataSection, Comment, DocId, DocKey, DocumentType, Text},
+    path,
     value::{ElementValue, NodeValue},
-    Node,
+    view::{ElementMut, ElementRef},
+    Node, Selector,
 };
 use tracing::debug;
 
 static ATTR_ID: Lazy<QName> = Lazy::new(|| QName::new("id").unwrap());
 
+/// A document's node storage, which tags every key it hands out with the document's identity.
+#[derive(Debug, Clone)]
+pub(crate) struct NodeMap {
+    map: SlotMap<DocKey, NodeValue>,
+    pub(crate) id: DocId,
+}
+
+impl NodeMap {
+    fn new() -> Self {
+        NodeMap {
+            map: SlotMap::with_key(),
+            id: DocId::next(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, value: NodeValue) -> DocKey {
+        self.map.insert(value).tagged(self.id)
+    }
+}
+
+impl Deref for NodeMap {
+    type Target = SlotMap<DocKey, NodeValue>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl DerefMut for NodeMap {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.map
+    }
+}
+
+/// An XML document.
+///
+/// Handles to its nodes, such as [`Element`], are only valid for the document that created them
+/// and for its clones. In debug builds or with the `checked-handles` feature, using a handle with
+/// another document is detected and reported as [`Error::WrongDocument`].
 #[derive(Debug, Clone)]
 pub struct Document {
-    pub(crate) nodes: SlotMap<DocKey, NodeValue>,
-    pub(crate) parents: SparseSecondaryMap<DocKey, Element>,
+    pub(crate) nodes: NodeMap,
+    pub(crate) parents: SecondaryMap<DocKey, Element>,
+    /// The index of each attached node among its siblings, or in the top-level sequence, so that
+    /// sibling lookups need no search. Kept up to date with [`Document::reindex`].
+    pub(crate) positions: SecondaryMap<DocKey, usize>,
     pub(crate) attrs: SparseSecondaryMap<DocKey, IndexMap<QName, String>>,
     pub(crate) root_key: Element,
     pub(crate) before: Vec<Node>,
@@ -125,33 +172,47 @@ pub fn ord_elem(ec1: &ElementAndContext, ec2: &ElementAndContext) -> Ordering {
 }
 
 impl Document {
+    /// Create a document with an empty root element.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `root_name` is not a valid qualified name. Use [`Document::try_new`] for a
+    /// fallible alternative.
     pub fn new(root_name: &str) -> Self {
-        let mut nodes = SlotMap::with_key();
-        let parents = SparseSecondaryMap::new();
+        match Self::try_new(root_name) {
+            Ok(document) => document,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub fn try_new(root_name: &str) -> Result<Self, Error> {
+        let mut nodes = NodeMap::new();
+        let parents = SecondaryMap::new();
         let attrs = SparseSecondaryMap::new();
 
         let root_key = Element(nodes.insert(NodeValue::Element(ElementValue {
-            name: root_name.parse().unwrap(),
+            name: parse_name(root_name)?,
             children: vec![],
         })));
 
-        Self {
+        Ok(Self {
             nodes,
             parents,
+            positions: SecondaryMap::new(),
             attrs,
             root_key,
             before: vec![],
             after: vec![],
             decl: None,
-        }
+        })
     }
 
-    fn sort_nodes(&self, nodes_orig: &Vec<Node>) -> Vec<Node> {
+    fn sort_nodes(&self, nodes_orig: &[Node]) -> Vec<Node> {
         if nodes_orig.len() < 2 {
-            return nodes_orig.clone();
+            return nodes_orig.to_vec();
         }
 
-        let nodes = nodes_orig.clone();
+        let nodes = nodes_orig.to_vec();
 
         let mut pre = vec![];
         let mut elems = vec![];
@@ -185,7 +246,7 @@ impl Document {
             we thus consider all elements to be inline-elements \
             (think of the *bold* element in HTML), and thus we will not sort them."
             );
-            return nodes_orig.clone();
+            return nodes_orig.to_vec();
         }
 
         elems.sort_by(ord_elem);
@@ -225,6 +286,232 @@ impl Document {
             self.before = self.sort_nodes(&self.before);
             self.nodes[self.root_key.0] = self.sort_node_value(&self.nodes[self.root_key.0]);
             self.after = self.sort_nodes(&self.after);
+            self.reindex_all();
+        }
+    }
+
+    /// Merge adjacent text nodes and remove empty ones throughout the document, like the DOM's
+    /// `Node.normalize()`.
+    ///
+    /// Merged text nodes are appended to the first node of each run. The others, and empty text
+    /// nodes, are freed, so their handles become stale.
+    ///
+    /// ```
+    /// let mut doc = "<root>a<b/></root>".parse::<xmlem::Document>().unwrap();
+    /// let root = doc.root();
+    /// root.append_text(&mut doc, "");
+    /// root.append_text(&mut doc, "c");
+    /// root.append_text(&mut doc, "d");
+    /// doc.normalize();
+    /// assert_eq!(root.child_nodes(&doc).len(), 3);
+    /// assert_eq!(doc.to_string(), "<root>a<b/>cd</root>");
+    /// ```
+    pub fn normalize(&mut self) {
+        let root = self.root();
+        let elements = std::iter::once(root)
+            .chain(root.walk(self))
+            .collect::<Vec<_>>();
+
+        for element in elements {
+            let children =
+                std::mem::take(&mut self.nodes[element.0].as_element_mut().unwrap().children);
+            let children = self.normalize_nodes(children);
+            self.nodes[element.0].as_element_mut().unwrap().children = children;
+        }
+
+        let before = std::mem::take(&mut self.before);
+        self.before = self.normalize_nodes(before);
+        let after = std::mem::take(&mut self.after);
+        self.after = self.normalize_nodes(after);
+        self.reindex_all();
+    }
+
+    fn normalize_nodes(&mut self, nodes: Vec<Node>) -> Vec<Node> {
+        let mut normalized: Vec<Node> = Vec::with_capacity(nodes.len());
+
+        for node in nodes {
+            let text = match node {
+                Node::Text(text) => text,
+                _ => {
+                    normalized.push(node);
+                    continue;
+                }
+            };
+
+            if let Some(Node::Text(prev)) = normalized.last().copied() {
+                let value = text.as_str(self).to_string();
+                self.nodes[prev.0].as_string_mut().unwrap().push_str(&value);
+                self.free_subtree(text.0);
+            } else if text.as_str(self).is_empty() {
+                self.free_subtree(text.0);
+            } else {
+                normalized.push(node);
+            }
+        }
+
+        normalized
+    }
+
+    /// Free every node that is not reachable from the root element, the nodes before or after it,
+    /// or one of the `keep` nodes.
+    ///
+    /// Detached nodes are otherwise kept alive so that they can be reinserted, which lets the
+    /// document grow unboundedly in long-running edits. Handles to freed nodes become stale. A
+    /// kept node whose detached parent is freed becomes detached itself.
+    ///
+    /// ```
+    /// let mut doc = "<root><a><b/></a><c/></root>".parse::<xmlem::Document>().unwrap();
+    /// let [a, c] = <[_; 2]>::try_from(doc.root().children(&doc)).unwrap();
+    /// a.as_node().detach(&mut doc);
+    /// c.as_node().detach(&mut doc);
+    /// doc.compact([c.as_node()]);
+    /// assert!(!doc.contains(a.as_node()));
+    /// assert!(doc.contains(c.as_node()));
+    /// ```
+    pub fn compact(&mut self, keep: impl IntoIterator<Item = Node>) {
+        let mut reachable = SparseSecondaryMap::<DocKey, ()>::new();
+        let mut stack = self
+            .before
+            .iter()
+            .chain(self.after.iter())
+            .map(|x| x.as_key())
+            .chain(std::iter::once(self.root_key.0))
+            .chain(
+                keep.into_iter()
+                    .map(Node::as_key)
+                    .filter(|x| x.belongs_to(self)),
+            )
+            .collect::<Vec<_>>();
+
+        while let Some(key) = stack.pop() {
+            if !self.nodes.contains_key(key) || reachable.insert(key, ()).is_some() {
+                continue;
+            }
+            if let Some(element) = self.nodes[key].as_element() {
+                stack.extend(element.children.iter().map(|x| x.as_key()));
+            }
+        }
+
+        self.nodes.retain(|key, _| reachable.contains_key(key));
+        self.attrs.retain(|key, _| reachable.contains_key(key));
+        let nodes = &self.nodes;
+        self.positions.retain(|key, _| nodes.contains_key(key));
+        self.parents
+            .retain(|key, parent| nodes.contains_key(key) && nodes.contains_key(parent.0));
+    }
+
+    /// Whether the node still exists in this document, i.e. has not been deleted or compacted
+    /// away.
+    pub fn contains(&self, node: Node) -> bool {
+        node.check(self).is_ok()
+    }
+
+    /// Copy a node and all of its descendants from another document into this one.
+    ///
+    /// The copy is detached; insert it with [`Element::append`] or similar. Handles from `other`
+    /// must never be used with this document directly.
+    ///
+    /// ```
+    /// let source = r#"<strings><string name="a">A<!--c--></string></strings>"#
+    ///     .parse::<xmlem::Document>()
+    ///     .unwrap();
+    /// let mut doc = "<root/>".parse::<xmlem::Document>().unwrap();
+    /// let string = source.root().children(&source)[0];
+    /// let copy = doc.import_node(&source, string.as_node());
+    /// doc.root().append(&mut doc, copy).unwrap();
+    /// assert_eq!(doc.to_string(), r#"<root><string name="a">A<!--c--></string></root>"#);
+    /// ```
+    pub fn import_node(&mut self, other: &Document, node: Node) -> Node {
+        match self.try_import_node(other, node) {
+            Ok(node) => node,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Like [`Document::import_node`], but fails if `node` does not exist in `other`.
+    pub fn try_import_node(&mut self, other: &Document, node: Node) -> Result<Node, Error> {
+        let entries = snapshot_subtree(other, node)?;
+        Ok(self.instantiate_subtree(entries))
+    }
+
+    /// Copy a node of this document and all of its descendants. The copy is detached.
+    pub(crate) fn clone_subtree(&mut self, node: Node) -> Result<Node, Error> {
+        let entries = snapshot_subtree(self, node)?;
+        Ok(self.instantiate_subtree(entries))
+    }
+
+    fn instantiate_subtree(&mut self, entries: Vec<SnapshotEntry>) -> Node {
+        let mut created: Vec<Node> = Vec::with_capacity(entries.len());
+
+        for entry in entries {
+            let key = self.nodes.insert(entry.value);
+            let node = match &self.nodes[key] {
+                NodeValue::Element(_) => Node::Element(Element(key)),
+                NodeValue::Text(_) => Node::Text(Text(key)),
+                NodeValue::CData(_) => Node::CDataSection(CDataSection(key)),
+                NodeValue::Comment(_) => Node::Comment(Comment(key)),
+                NodeValue::DocumentType(_) => Node::DocumentType(DocumentType(key)),
+            };
+            if let Some(attrs) = entry.attrs {
+                self.attrs.insert(key, attrs);
+            }
+            if let Some(parent) = entry.parent {
+                let parent = created[parent].as_element().unwrap();
+                self.parents.insert(key, parent);
+                let children = &mut self.nodes[parent.0].as_element_mut().unwrap().children;
+                children.push(node);
+                self.positions.insert(key, children.len() - 1);
+            }
+            created.push(node);
+        }
+
+        created[0]
+    }
+
+    /// Free the node with the given key and all of its descendants. The node must already be
+    /// detached.
+    pub(crate) fn free_subtree(&mut self, key: DocKey) {
+        let mut stack = vec![key];
+        while let Some(key) = stack.pop() {
+            if let Some(NodeValue::Element(element)) = self.nodes.remove(key) {
+                stack.extend(element.children.iter().map(|x| x.as_key()));
+            }
+            self.parents.remove(key);
+            self.positions.remove(key);
+            self.attrs.remove(key);
+        }
+    }
+
+    /// Record the positions of `parent`'s children from `start` on, after some of them moved. With
+    /// no parent, the positions of the nodes before and after the root element are recorded.
+    pub(crate) fn reindex(&mut self, parent: Option<Element>, start: usize) {
+        match parent {
+            Some(parent) => {
+                let children = &self.nodes[parent.0].as_element().unwrap().children;
+                for (i, node) in children.iter().enumerate().skip(start) {
+                    self.positions.insert(node.as_key(), i);
+                }
+            }
+            None => {
+                for (i, node) in self.before.iter().enumerate() {
+                    self.positions.insert(node.as_key(), i);
+                }
+                let offset = self.before.len() + 1;
+                for (i, node) in self.after.iter().enumerate() {
+                    self.positions.insert(node.as_key(), offset + i);
+                }
+            }
+        }
+    }
+
+    fn reindex_all(&mut self) {
+        self.reindex(None, 0);
+        for (_, value) in self.nodes.iter() {
+            if let Some(element) = value.as_element() {
+                for (i, node) in element.children.iter().enumerate() {
+                    self.positions.insert(node.as_key(), i);
+                }
+            }
         }
     }
 
@@ -237,32 +524,33 @@ impl Document {
     }
 
     pub fn set_doctype(&mut self, doctype: Option<&str>) {
-        match doctype {
-            Some(v) => {
+        let existing = self
+            .before
+            .iter()
+            .position(|x| x.as_document_type().is_some());
+
+        match (doctype, existing) {
+            (Some(v), Some(i)) => {
+                let id = Node::DocumentType(DocumentType(
+                    self.nodes.insert(NodeValue::DocumentType(v.to_string())),
+                ));
+                self.nodes.remove(self.before[i].as_key());
+                self.before[i] = id;
+            }
+            (Some(v), None) => {
                 let id = Node::DocumentType(DocumentType(
                     self.nodes.insert(NodeValue::DocumentType(v.to_string())),
                 ));
-
-                for i in 0..self.before.len() {
-                    if self.before[i].as_document_type().is_some() {
-                        self.nodes.remove(self.before[i].as_key());
-                        self.before[i] = id;
-                        return;
-                    }
-                }
-
-                // If that failed, push it ourselves.
                 self.before.insert(0, id);
             }
-            None => {
-                for i in 0..self.before.len() {
-                    if self.before[i].as_document_type().is_some() {
-                        self.before.remove(i);
-                        return;
-                    }
-                }
+            (None, Some(i)) => {
+                let node = self.before.remove(i);
+                self.nodes.remove(node.as_key());
             }
+            (None, None) => {}
         }
+
+        self.reindex(None, 0);
     }
 
     pub fn doctype(&self) -> Option<&str> {
@@ -280,6 +568,73 @@ impl Document {
         self.root_key
     }
 
+    /// Find the element a path from [`Element::path`] or [`Element::path_with_keys`] refers to.
+    ///
+    /// Positions (`[2]`) and attribute predicates (`[@id='x']`) may be combined and are applied in
+    /// order. If a step matches several elements, the first is used.
+    pub fn resolve_path(&self, path: &str) -> Option<Element> {
+        path::resolve_path(self, path)
+    }
+
+    /// The first element matching `selector` in document order, including the root.
+    ///
+    /// ```
+    /// use xmlem::{Document, Selector};
+    ///
+    /// let doc = r#"<resources><string/></resources>"#.parse::<Document>().unwrap();
+    /// let selector = Selector::new("resources, string").unwrap();
+    /// assert_eq!(doc.query_selector(&selector), Some(doc.root()));
+    /// assert_eq!(doc.query_selector_all(&selector).len(), 2);
+    /// ```
+    pub fn query_selector(&self, selector: &Selector) -> Option<Element> {
+        self.query_selector_iter(selector).next()
+    }
+
+    pub fn query_selector_all(&self, selector: &Selector) -> Vec<Element> {
+        self.query_selector_iter(selector).collect()
+    }
+
+    /// The elements matching `selector`, lazily and in document order, including the root.
+    pub fn query_selector_iter<'d>(&'d self, selector: &'d Selector) -> QuerySelectorIter<'d> {
+        QuerySelectorIter::new(self, selector, self.root(), true)
+    }
+
+    pub fn root_ref(&self) -> ElementRef<'_> {
+        self.root().with(self)
+    }
+
+    pub fn root_mut(&mut self) -> ElementMut<'_> {
+        self.root().with_mut(self)
+    }
+
+    /// Create a new element that is not yet attached to the tree.
+    ///
+    /// The element can be attached with [`Element::append`], [`Element::insert_before`] and friends.
+    pub fn create_element(&mut self, element: impl Into<NewElement>) -> Element {
+        let element = element.into();
+        let new_key = self.nodes.insert(NodeValue::Element(ElementValue {
+            name: element.name,
+            children: vec![],
+        }));
+        self.attrs.insert(new_key, element.attrs);
+        Element(new_key)
+    }
+
+    /// Create a new text node that is not yet attached to the tree.
+    pub fn create_text(&mut self, text: &str) -> Text {
+        Text(self.nodes.insert(NodeValue::Text(text.to_string())))
+    }
+
+    /// Create a new CDATA section that is not yet attached to the tree.
+    pub fn create_cdata(&mut self, text: &str) -> CDataSection {
+        CDataSection(self.nodes.insert(NodeValue::CData(text.to_string())))
+    }
+
+    /// Create a new comment that is not yet attached to the tree.
+    pub fn create_comment(&mut self, text: &str) -> Comment {
+        Comment(self.nodes.insert(NodeValue::Comment(text.to_string())))
+    }
+
     #[inline]
     pub fn to_string_pretty(&self) -> String {
         let mut s = vec![];
@@ -311,8 +666,8 @@ impl Document {
 
         let mut decl: Option<Declaration> = None;
 
-        let mut nodes = SlotMap::with_key();
-        let parents = SparseSecondaryMap::new();
+        let mut nodes = NodeMap::new();
+        let parents = SecondaryMap::new();
         let attrs = SparseSecondaryMap::new();
 
         let mut before: Vec<Node> = vec![];
@@ -356,6 +711,7 @@ impl Document {
                     let mut document = Document {
                         nodes,
                         parents,
+                        positions: SecondaryMap::new(),
                         attrs,
                         root_key,
                         decl,
@@ -379,7 +735,7 @@ impl Document {
                     break document;
                 }
                 Ok(Event::Text(e)) => {
-                    if e.len() == 0 {
+                    if e.is_empty() {
                         continue;
                     }
                     if e.unescape().map(|x| x.trim().is_empty()).unwrap_or(false) {
@@ -428,8 +784,7 @@ impl Document {
                         let value = attr.unescape_value()?.to_string();
                         attrs.insert(std::str::from_utf8(attr.key.into_inner())?.parse()?, value);
                     }
-                    let element =
-                        parent.append_new_element(&mut doc, crate::NewElement { name, attrs });
+                    let element = parent.append_new_element(&mut doc, NewElement { name, attrs });
                     element_stack.push(element);
                 }
                 Ok(Event::Empty(e)) => {
@@ -447,7 +802,7 @@ impl Document {
                         let value = attr.unescape_value()?.to_string();
                         attrs.insert(std::str::from_utf8(attr.key.into_inner())?.parse()?, value);
                     }
-                    parent.append_new_element(&mut doc, crate::NewElement { name, attrs });
+                    parent.append_new_element(&mut doc, NewElement { name, attrs });
                 }
                 Ok(Event::End(_e)) => {
                     element_stack.pop();
@@ -513,10 +868,49 @@ impl Document {
             }
         }
 
+        doc.reindex(None, 0);
         Ok(doc)
     }
 }
 
+/// A copy of a single node, with its children left out, as part of a pre-order listing of a
+/// subtree.
+struct SnapshotEntry {
+    value: NodeValue,
+    attrs: Option<IndexMap<QName, String>>,
+    /// Index of the parent within the listing; `None` for the subtree's root.
+    parent: Option<usize>,
+}
+
+fn snapshot_subtree(document: &Document, node: Node) -> Result<Vec<SnapshotEntry>, Error> {
+    node.check(document)?;
+
+    let mut entries = vec![];
+    let mut stack = vec![(node, None)];
+
+    while let Some((node, parent)) = stack.pop() {
+        let key = node.as_key();
+        let value = match &document.nodes[key] {
+            NodeValue::Element(element) => {
+                let index = entries.len();
+                stack.extend(element.children.iter().rev().map(|x| (*x, Some(index))));
+                NodeValue::Element(ElementValue {
+                    name: element.name.clone(),
+                    children: vec![],
+                })
+            }
+            other => other.clone(),
+        };
+        entries.push(SnapshotEntry {
+            value,
+            attrs: document.attrs.get(key).cloned(),
+            parent,
+        });
+    }
+
+    Ok(entries)
+}
+
 impl std::str::FromStr for Document {
     type Err = ReadError;
 
@@ -549,8 +943,8 @@ impl fmt::Display for ReadError {
     }
 }
 
-impl Error for ReadError {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
+impl error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         if let Self::Parse(err) = self {
             err.source()
         } else {
@@ -1,3 +1,9 @@
+use std::{
+    fmt::Display,
+    ops::{Bound, RangeBounds},
+    str::{FromStr, ParseBoolError, SplitWhitespace},
+};
+
 use indexmap::IndexMap;
 use once_cell::sync::Lazy;
 use qname::QName;
@@ -5,9 +11,16 @@ use qname::QName;
 use crate::{
     display::{self, Print},
     document::Document,
+    error::{parse_name, AttributeError, Error},
+    iter::{
+        Ancestors, ChildElements, DescendantNodes, Descendants, Elements, FollowingSiblingElements,
+        PrecedingSiblingElements, QuerySelectorIter, Traverse,
+    },
     key::{CDataSection, Comment, DocKey, Node, Text},
-    select::Selector,
+    path,
+    select::{MatchingCaches, Selector},
     value::{ElementValue, NodeValue},
+    view::{ElementMut, ElementRef},
 };
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -25,6 +38,35 @@ pub struct NewElement {
     pub attrs: IndexMap<QName, String>,
 }
 
+impl NewElement {
+    /// Create a new element description from unparsed names.
+    ///
+    /// ```
+    /// use xmlem::{Error, NewElement};
+    /// assert!(NewElement::try_new("Row", [("x", "1")]).is_ok());
+    /// assert_eq!(
+    ///     NewElement::try_new("Row", [("a b", "1")]).unwrap_err(),
+    ///     Error::InvalidName("a b".into())
+    /// );
+    /// ```
+    pub fn try_new<K: AsRef<str>, V: ToString>(
+        name: &str,
+        attrs: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<NewElement, Error> {
+        Ok(NewElement {
+            name: parse_name(name)?,
+            attrs: attrs
+                .into_iter()
+                .map(|(k, v)| Ok((parse_name(k.as_ref())?, v.to_string())))
+                .collect::<Result<_, Error>>()?,
+        })
+    }
+}
+
+/// # Panics
+///
+/// Panics if the element or an attribute name is not a valid qualified name. Use
+/// [`NewElement::try_new`] for a fallible alternative.
 impl<const N: usize, T: ToString, U: ToString, V: ToString> From<(T, [(U, V); N])> for NewElement {
     fn from(x: (T, [(U, V); N])) -> Self {
         NewElement {
@@ -43,20 +85,60 @@ impl Element {
         Node::from(*self)
     }
 
+    /// View this element together with its document. See [`ElementRef`].
+    pub fn with(self, document: &Document) -> ElementRef<'_> {
+        ElementRef::new(document, self)
+    }
+
+    /// View this element together with its document, borrowed mutably. See [`ElementMut`].
+    pub fn with_mut(self, document: &mut Document) -> ElementMut<'_> {
+        ElementMut::new(document, self)
+    }
+
+    pub(crate) fn try_value(self, document: &Document) -> Result<&ElementValue, Error> {
+        if !self.0.belongs_to(document) {
+            return Err(Error::WrongDocument(self.as_node()));
+        }
+
+        match document.nodes.get(self.0) {
+            Some(NodeValue::Element(element)) => Ok(element),
+            Some(_) => Err(Error::WrongNodeType(self.as_node())),
+            None => Err(Error::NodeNotFound(self.as_node())),
+        }
+    }
+
+    pub(crate) fn try_value_mut(self, document: &mut Document) -> Result<&mut ElementValue, Error> {
+        if !self.0.belongs_to(document) {
+            return Err(Error::WrongDocument(self.as_node()));
+        }
+
+        match document.nodes.get_mut(self.0) {
+            Some(NodeValue::Element(element)) => Ok(element),
+            Some(_) => Err(Error::WrongNodeType(self.as_node())),
+            None => Err(Error::NodeNotFound(self.as_node())),
+        }
+    }
+
+    pub(crate) fn value(self, document: &Document) -> &ElementValue {
+        match self.try_value(document) {
+            Ok(element) => element,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub(crate) fn value_mut(self, document: &mut Document) -> &mut ElementValue {
+        match self.try_value_mut(document) {
+            Ok(element) => element,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
     pub fn append_element(self, document: &mut Document, element: Element) {
         if let Some(parent) = element.parent(document) {
             parent.remove_child(document, Node::Element(element));
         }
 
-        document.parents.insert(element.0, self);
-        document
-            .nodes
-            .get_mut(self.0)
-            .unwrap()
-            .as_element_mut()
-            .unwrap()
-            .children
-            .push(Node::Element(element));
+        self.push_child(document, Node::Element(element));
     }
 
     pub fn append_new_element(
@@ -70,131 +152,730 @@ impl Element {
             children: vec![],
         }));
         document.attrs.insert(new_key, element.attrs);
-        document.parents.insert(new_key, self);
-        document
-            .nodes
-            .get_mut(self.0)
-            .unwrap()
-            .as_element_mut()
-            .unwrap()
-            .children
-            .push(Node::Element(Element(new_key)));
+        self.push_child(document, Node::Element(Element(new_key)));
         Element(new_key)
     }
 
+    /// Insert a new element as the next sibling of this element.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this element has no parent, such as the root element. Use
+    /// [`Element::try_append_new_element_after`] for a fallible alternative.
     pub fn append_new_element_after(
         self,
         document: &mut Document,
         new_element: impl Into<NewElement>,
     ) -> Element {
-        let element = new_element.into();
-        let new_key = document.nodes.insert(NodeValue::Element(ElementValue {
-            name: element.name,
-            children: vec![],
-        }));
-        document.attrs.insert(new_key, element.attrs);
-
-        let parent = self.parent(document).expect("no parent");
-        document.parents.insert(new_key, parent);
-
-        let children = &mut document
-            .nodes
-            .get_mut(parent.0)
-            .unwrap()
-            .as_element_mut()
-            .unwrap()
-            .children;
-
-        let index = children.iter().position(|x| x == &self.as_node()).unwrap() + 1;
-        if index >= children.len() {
-            children.push(Node::Element(Element(new_key)));
-        } else {
-            children.insert(index, Node::Element(Element(new_key)))
+        match self.try_append_new_element_after(document, new_element) {
+            Ok(element) => element,
+            Err(e) => panic!("{e}"),
         }
+    }
 
-        Element(new_key)
+    /// Insert a new element as the next sibling of this element, failing without creating it if
+    /// this element is the root or detached.
+    ///
+    /// ```
+    /// use xmlem::{Document, Error};
+    ///
+    /// let mut doc = "<root><a/></root>".parse::<Document>().unwrap();
+    /// let root = doc.root();
+    /// let a = root.children(&doc)[0];
+    /// a.try_append_new_element_after(&mut doc, ("b", [("n", "1")])).unwrap();
+    /// assert_eq!(
+    ///     root.try_append_new_element_after(&mut doc, ("c", [("n", "2")])),
+    ///     Err(Error::Hierarchy(root.as_node()))
+    /// );
+    /// assert_eq!(doc.to_string(), r#"<root><a/><b n="1"/></root>"#);
+    /// ```
+    pub fn try_append_new_element_after(
+        self,
+        document: &mut Document,
+        new_element: impl Into<NewElement>,
+    ) -> Result<Element, Error> {
+        self.try_value(document)?;
+        match self.parent(document) {
+            Some(parent) => parent.insert_new_element_after(document, self, new_element),
+            None if self == document.root() => Err(Error::Hierarchy(self.as_node())),
+            None => Err(Error::Detached(self.as_node())),
+        }
     }
 
     pub fn append_text(self, document: &mut Document, text: &str) -> Text {
         let new_key = document.nodes.insert(NodeValue::Text(text.to_string()));
-        document.parents.insert(new_key, self);
-        document
-            .nodes
-            .get_mut(self.0)
-            .unwrap()
-            .as_element_mut()
-            .unwrap()
-            .children
-            .push(Node::Text(Text(new_key)));
+        self.push_child(document, Node::Text(Text(new_key)));
         Text(new_key)
     }
 
     pub fn append_cdata(self, document: &mut Document, text: &str) -> CDataSection {
         let new_key = document.nodes.insert(NodeValue::CData(text.to_string()));
-        document.parents.insert(new_key, self);
-        document
-            .nodes
-            .get_mut(self.0)
-            .unwrap()
-            .as_element_mut()
-            .unwrap()
-            .children
-            .push(Node::CDataSection(CDataSection(new_key)));
+        self.push_child(document, Node::CDataSection(CDataSection(new_key)));
         CDataSection(new_key)
     }
 
     pub fn append_comment(self, document: &mut Document, text: &str) -> Comment {
         let new_key = document.nodes.insert(NodeValue::Comment(text.to_string()));
-        document.parents.insert(new_key, self);
-        document
-            .nodes
-            .get_mut(self.0)
-            .unwrap()
-            .as_element_mut()
-            .unwrap()
-            .children
-            .push(Node::Comment(Comment(new_key)));
+        self.push_child(document, Node::Comment(Comment(new_key)));
         Comment(new_key)
     }
 
+    pub fn try_append_new_element(
+        self,
+        document: &mut Document,
+        element: impl Into<NewElement>,
+    ) -> Result<Element, Error> {
+        self.try_value(document)?;
+        Ok(self.append_new_element(document, element))
+    }
+
+    pub fn try_append_text(self, document: &mut Document, text: &str) -> Result<Text, Error> {
+        self.try_value(document)?;
+        Ok(self.append_text(document, text))
+    }
+
+    pub fn try_append_cdata(
+        self,
+        document: &mut Document,
+        text: &str,
+    ) -> Result<CDataSection, Error> {
+        self.try_value(document)?;
+        Ok(self.append_cdata(document, text))
+    }
+
+    pub fn try_append_comment(self, document: &mut Document, text: &str) -> Result<Comment, Error> {
+        self.try_value(document)?;
+        Ok(self.append_comment(document, text))
+    }
+
+    /// Append an existing node as the last child of this element.
+    ///
+    /// If the node is already attached elsewhere, it is moved.
+    pub fn append(self, document: &mut Document, node: impl Into<Node>) -> Result<(), Error> {
+        let node = node.into();
+        self.check_insertable(document, node)?;
+        node.detach(document);
+        let index = self.child_nodes(document).len();
+        self.link_child(document, index, node);
+        Ok(())
+    }
+
+    /// Insert an existing node as the first child of this element.
+    ///
+    /// If the node is already attached elsewhere, it is moved.
+    pub fn prepend(self, document: &mut Document, node: impl Into<Node>) -> Result<(), Error> {
+        self.insert_at(document, 0, node)
+    }
+
+    /// Insert an existing node so that it becomes the child at `index`.
+    ///
+    /// If the node is already attached elsewhere, it is moved. If it is already a child of this
+    /// element, `index` refers to the position it is inserted at after having been removed.
+    ///
+    /// ```
+    /// let mut doc = "<root><a/><c/></root>".parse::<xmlem::Document>().unwrap();
+    /// let b = doc.create_element(("b", [("x", "1")]));
+    /// doc.root().insert_at(&mut doc, 1, b).unwrap();
+    /// assert_eq!(doc.to_string(), "<root><a/><b x=\"1\"/><c/></root>");
+    /// assert!(doc.root().insert_at(&mut doc, 9, b).is_err());
+    /// ```
+    pub fn insert_at(
+        self,
+        document: &mut Document,
+        index: usize,
+        node: impl Into<Node>,
+    ) -> Result<(), Error> {
+        let node = node.into();
+        self.check_index(document, index)?;
+        self.check_insertable(document, node)?;
+        node.detach(document);
+        let len = self.child_nodes(document).len();
+        self.link_child(document, index.min(len), node);
+        Ok(())
+    }
+
+    /// Insert an existing node immediately before `reference`, which must be a child of this
+    /// element.
+    ///
+    /// If the node is already attached elsewhere, it is moved.
+    pub fn insert_before(
+        self,
+        document: &mut Document,
+        reference: impl Into<Node>,
+        node: impl Into<Node>,
+    ) -> Result<(), Error> {
+        let (reference, node) = (reference.into(), node.into());
+        self.child_position(document, reference)?;
+        self.check_insertable(document, node)?;
+        if reference == node {
+            return Ok(());
+        }
+        node.detach(document);
+        let index = self.child_position(document, reference)?;
+        self.link_child(document, index, node);
+        Ok(())
+    }
+
+    /// Insert an existing node immediately after `reference`, which must be a child of this
+    /// element.
+    ///
+    /// If the node is already attached elsewhere, it is moved.
+    pub fn insert_after(
+        self,
+        document: &mut Document,
+        reference: impl Into<Node>,
+        node: impl Into<Node>,
+    ) -> Result<(), Error> {
+        let (reference, node) = (reference.into(), node.into());
+        self.child_position(document, reference)?;
+        self.check_insertable(document, node)?;
+        if reference == node {
+            return Ok(());
+        }
+        node.detach(document);
+        let index = self.child_position(document, reference)? + 1;
+        self.link_child(document, index, node);
+        Ok(())
+    }
+
+    pub fn prepend_new_element(
+        self,
+        document: &mut Document,
+        element: impl Into<NewElement>,
+    ) -> Element {
+        let element = document.create_element(element);
+        self.link_child(document, 0, element.as_node());
+        element
+    }
+
+    pub fn prepend_text(self, document: &mut Document, text: &str) -> Text {
+        let text = document.create_text(text);
+        self.link_child(document, 0, Node::Text(text));
+        text
+    }
+
+    pub fn prepend_cdata(self, document: &mut Document, text: &str) -> CDataSection {
+        let cdata = document.create_cdata(text);
+        self.link_child(document, 0, Node::CDataSection(cdata));
+        cdata
+    }
+
+    pub fn prepend_comment(self, document: &mut Document, text: &str) -> Comment {
+        let comment = document.create_comment(text);
+        self.link_child(document, 0, Node::Comment(comment));
+        comment
+    }
+
+    pub fn insert_new_element_at(
+        self,
+        document: &mut Document,
+        index: usize,
+        element: impl Into<NewElement>,
+    ) -> Result<Element, Error> {
+        self.check_index(document, index)?;
+        let element = document.create_element(element);
+        self.link_child(document, index, element.as_node());
+        Ok(element)
+    }
+
+    pub fn insert_text_at(
+        self,
+        document: &mut Document,
+        index: usize,
+        text: &str,
+    ) -> Result<Text, Error> {
+        self.check_index(document, index)?;
+        let text = document.create_text(text);
+        self.link_child(document, index, Node::Text(text));
+        Ok(text)
+    }
+
+    pub fn insert_cdata_at(
+        self,
+        document: &mut Document,
+        index: usize,
+        text: &str,
+    ) -> Result<CDataSection, Error> {
+        self.check_index(document, index)?;
+        let cdata = document.create_cdata(text);
+        self.link_child(document, index, Node::CDataSection(cdata));
+        Ok(cdata)
+    }
+
+    pub fn insert_comment_at(
+        self,
+        document: &mut Document,
+        index: usize,
+        text: &str,
+    ) -> Result<Comment, Error> {
+        self.check_index(document, index)?;
+        let comment = document.create_comment(text);
+        self.link_child(document, index, Node::Comment(comment));
+        Ok(comment)
+    }
+
+    pub fn insert_new_element_before(
+        self,
+        document: &mut Document,
+        reference: impl Into<Node>,
+        element: impl Into<NewElement>,
+    ) -> Result<Element, Error> {
+        let index = self.child_position(document, reference.into())?;
+        self.insert_new_element_at(document, index, element)
+    }
+
+    pub fn insert_text_before(
+        self,
+        document: &mut Document,
+        reference: impl Into<Node>,
+        text: &str,
+    ) -> Result<Text, Error> {
+        let index = self.child_position(document, reference.into())?;
+        self.insert_text_at(document, index, text)
+    }
+
+    pub fn insert_cdata_before(
+        self,
+        document: &mut Document,
+        reference: impl Into<Node>,
+        text: &str,
+    ) -> Result<CDataSection, Error> {
+        let index = self.child_position(document, reference.into())?;
+        self.insert_cdata_at(document, index, text)
+    }
+
+    pub fn insert_comment_before(
+        self,
+        document: &mut Document,
+        reference: impl Into<Node>,
+        text: &str,
+    ) -> Result<Comment, Error> {
+        let index = self.child_position(document, reference.into())?;
+        self.insert_comment_at(document, index, text)
+    }
+
+    pub fn insert_new_element_after(
+        self,
+        document: &mut Document,
+        reference: impl Into<Node>,
+        element: impl Into<NewElement>,
+    ) -> Result<Element, Error> {
+        let index = self.child_position(document, reference.into())?;
+        self.insert_new_element_at(document, index + 1, element)
+    }
+
+    pub fn insert_text_after(
+        self,
+        document: &mut Document,
+        reference: impl Into<Node>,
+        text: &str,
+    ) -> Result<Text, Error> {
+        let index = self.child_position(document, reference.into())?;
+        self.insert_text_at(document, index + 1, text)
+    }
+
+    pub fn insert_cdata_after(
+        self,
+        document: &mut Document,
+        reference: impl Into<Node>,
+        text: &str,
+    ) -> Result<CDataSection, Error> {
+        let index = self.child_position(document, reference.into())?;
+        self.insert_cdata_at(document, index + 1, text)
+    }
+
+    pub fn insert_comment_after(
+        self,
+        document: &mut Document,
+        reference: impl Into<Node>,
+        text: &str,
+    ) -> Result<Comment, Error> {
+        let index = self.child_position(document, reference.into())?;
+        self.insert_comment_at(document, index + 1, text)
+    }
+
+    fn child_position(self, document: &Document, reference: Node) -> Result<usize, Error> {
+        self.try_value(document)?;
+        if reference.as_key().belongs_to(document) && reference.parent(document) == Some(self) {
+            Ok(reference.index_in_parent(document).unwrap())
+        } else {
+            Err(Error::NotAChild(reference))
+        }
+    }
+
+    fn check_index(self, document: &Document, index: usize) -> Result<(), Error> {
+        let len = self.try_child_nodes(document)?.len();
+        if index > len {
+            return Err(Error::OutOfBounds { index, len });
+        }
+        Ok(())
+    }
+
+    /// Ensures that both handles are valid and that `node` may become a child of this element: it
+    /// must not be the root, a document type, or this element or any of its ancestors.
+    fn check_insertable(self, document: &Document, node: Node) -> Result<(), Error> {
+        self.try_value(document)?;
+        node.check(document)?;
+        match node {
+            Node::DocumentType(_) => return Err(Error::Hierarchy(node)),
+            Node::Element(e) if e == document.root() => return Err(Error::Hierarchy(node)),
+            Node::Element(e) => {
+                let mut current = Some(self);
+                while let Some(ancestor) = current {
+                    if ancestor == e {
+                        return Err(Error::Hierarchy(node));
+                    }
+                    current = ancestor.parent(document);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn link_child(self, document: &mut Document, index: usize, node: Node) {
+        document.parents.insert(node.as_key(), self);
+        self.children_mut(document).insert(index, node);
+        document.reindex(Some(self), index);
+    }
+
+    fn push_child(self, document: &mut Document, node: Node) {
+        document.parents.insert(node.as_key(), self);
+        let children = self.children_mut(document);
+        children.push(node);
+        let index = children.len() - 1;
+        document.positions.insert(node.as_key(), index);
+    }
+
+    pub(crate) fn children_mut(self, document: &mut Document) -> &mut Vec<Node> {
+        &mut self.value_mut(document).children
+    }
+
+    /// Wrap this element in a new element, which takes its place.
+    ///
+    /// Wrapping the root element makes the new element the root. Wrapping a detached element
+    /// returns a detached wrapper.
+    ///
+    /// ```
+    /// let mut doc = "<root><a/><b/></root>".parse::<xmlem::Document>().unwrap();
+    /// let b = doc.root().children(&doc)[1];
+    /// b.wrap(&mut doc, ("Row", [("x", "1")])).unwrap();
+    /// assert_eq!(doc.to_string(), r#"<root><a/><Row x="1"><b/></Row></root>"#);
+    /// ```
+    pub fn wrap(
+        self,
+        document: &mut Document,
+        element: impl Into<NewElement>,
+    ) -> Result<Element, Error> {
+        self.try_value(document)?;
+        if let Some(index) = self.as_node().index_in_parent(document) {
+            if let Some(parent) = self.parent(document) {
+                return parent.wrap_children_range(document, index..=index, element);
+            }
+        }
+
+        let wrapper = document.create_element(element);
+        if self == document.root() {
+            document.root_key = wrapper;
+        }
+        wrapper.link_child(document, 0, self.as_node());
+        Ok(wrapper)
+    }
+
+    /// Move all children of this element into a new element, which becomes its only child.
+    ///
+    /// ```
+    /// let mut doc = "<root>a<b/></root>".parse::<xmlem::Document>().unwrap();
+    /// doc.root().wrap_children(&mut doc, ("inner", [("x", "1")]));
+    /// assert_eq!(doc.to_string(), r#"<root><inner x="1">a<b/></inner></root>"#);
+    /// ```
+    pub fn wrap_children(self, document: &mut Document, element: impl Into<NewElement>) -> Element {
+        self.wrap_children_range(document, .., element).unwrap()
+    }
+
+    /// Move a contiguous range of this element's children into a new element, which takes their
+    /// place.
+    ///
+    /// ```
+    /// let mut doc = "<merge><a/><include/><include/><b/></merge>".parse::<xmlem::Document>().unwrap();
+    /// doc.root().wrap_children_range(&mut doc, 1..3, ("Row", [("x", "1")])).unwrap();
+    /// assert_eq!(
+    ///     doc.to_string(),
+    ///     r#"<merge><a/><Row x="1"><include/><include/></Row><b/></merge>"#
+    /// );
+    /// ```
+    pub fn wrap_children_range(
+        self,
+        document: &mut Document,
+        range: impl RangeBounds<usize>,
+        element: impl Into<NewElement>,
+    ) -> Result<Element, Error> {
+        let len = self.try_child_nodes(document)?.len();
+        let start = match range.start_bound() {
+            Bound::Included(&x) => x,
+            Bound::Excluded(&x) => x + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&x) => x + 1,
+            Bound::Excluded(&x) => x,
+            Bound::Unbounded => len,
+        };
+        if end > len {
+            return Err(Error::OutOfBounds { index: end, len });
+        }
+        if start > end {
+            return Err(Error::OutOfBounds { index: start, len });
+        }
+
+        let wrapper = document.create_element(element);
+        let moved = self
+            .children_mut(document)
+            .splice(start..end, [wrapper.as_node()])
+            .collect::<Vec<_>>();
+        document.parents.insert(wrapper.0, self);
+        for node in moved.iter() {
+            document.parents.insert(node.as_key(), wrapper);
+        }
+        *wrapper.children_mut(document) = moved;
+        document.reindex(Some(self), start);
+        document.reindex(Some(wrapper), 0);
+
+        Ok(wrapper)
+    }
+
+    /// Replace this element with its children, which are moved into its parent.
+    ///
+    /// The element itself is left detached and empty. The root element cannot be unwrapped.
+    ///
+    /// ```
+    /// let mut doc = "<root><a/><group>b<c/></group></root>".parse::<xmlem::Document>().unwrap();
+    /// let group = doc.root().children(&doc)[1];
+    /// group.unwrap(&mut doc).unwrap();
+    /// assert_eq!(doc.to_string(), "<root><a/>b<c/></root>");
+    /// ```
+    pub fn unwrap(self, document: &mut Document) -> Result<(), Error> {
+        self.try_value(document)?;
+        let parent = match self.parent(document) {
+            Some(parent) => parent,
+            None if self == document.root() => return Err(Error::Hierarchy(self.as_node())),
+            None => return Err(Error::Detached(self.as_node())),
+        };
+
+        let index = parent.child_position(document, self.as_node())?;
+        let children = std::mem::take(self.children_mut(document));
+        for node in children.iter() {
+            document.parents.insert(node.as_key(), parent);
+        }
+        parent
+            .children_mut(document)
+            .splice(index..=index, children);
+        document.parents.remove(self.0);
+        document.positions.remove(self.0);
+        document.reindex(Some(parent), index);
+
+        Ok(())
+    }
+
+    /// Replace all children of this element with a single text node.
+    ///
+    /// The previous children and their descendants are freed.
     pub fn set_text(self, document: &mut Document, text: &str) {
-        let new_key = document.nodes.insert(NodeValue::Text(text.to_string()));
-        document
-            .nodes
-            .get_mut(self.0)
-            .unwrap()
-            .as_element_mut()
-            .unwrap()
-            .children = vec![Node::Text(Text(new_key))];
+        let text = document.create_text(text);
+        self.replace_children(document, vec![Node::Text(text)]);
     }
 
-    pub fn remove_child(self, document: &mut Document, node: Node) {
-        let element = document
-            .nodes
-            .get_mut(self.0)
-            .unwrap()
-            .as_element_mut()
+    pub fn try_set_text(self, document: &mut Document, text: &str) -> Result<(), Error> {
+        self.try_value(document)?;
+        self.set_text(document, text);
+        Ok(())
+    }
+
+    /// Replace all children of this element with the given text, like the DOM's `textContent`
+    /// setter.
+    ///
+    /// The previous children and their descendants are freed. An empty string leaves the element
+    /// without children.
+    ///
+    /// ```
+    /// let mut doc = "<root><a>b</a></root>".parse::<xmlem::Document>().unwrap();
+    /// doc.root().set_text_content(&mut doc, "c & d");
+    /// assert_eq!(doc.to_string(), "<root>c &amp; d</root>");
+    /// doc.root().set_text_content(&mut doc, "");
+    /// assert_eq!(doc.to_string(), "<root/>");
+    /// ```
+    pub fn set_text_content(self, document: &mut Document, text: &str) {
+        let children = if text.is_empty() {
+            vec![]
+        } else {
+            vec![Node::Text(document.create_text(text))]
+        };
+        self.replace_children(document, children);
+    }
+
+    fn replace_children(self, document: &mut Document, children: Vec<Node>) {
+        for child in children.iter() {
+            document.parents.insert(child.as_key(), self);
+        }
+        let old_children = std::mem::replace(&mut self.value_mut(document).children, children);
+        document.reindex(Some(self), 0);
+        for child in old_children {
+            document.free_subtree(child.as_key());
+        }
+    }
+
+    /// The concatenated text and CDATA content of all descendants of this element, in document
+    /// order.
+    ///
+    /// ```
+    /// let doc = r#"<string name="x">Hello <b>world<![CDATA[!]]></b></string>"#
+    ///     .parse::<xmlem::Document>()
+    ///     .unwrap();
+    /// assert_eq!(doc.root().text_content(&doc), "Hello world!");
+    /// ```
+    pub fn text_content(self, document: &Document) -> String {
+        let mut text = String::new();
+        let mut stack = vec![self.child_nodes(document).iter()];
+
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(Node::Element(e)) => stack.push(e.child_nodes(document).iter()),
+                Some(Node::Text(t)) => text.push_str(t.as_str(document)),
+                Some(Node::CDataSection(t)) => text.push_str(t.as_str(document)),
+                Some(_) => {}
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+
+        text
+    }
+
+    /// The concatenated text and CDATA children of this element, ignoring descendants.
+    ///
+    /// ```
+    /// let doc = "<string>Hello <b>big</b> world</string>".parse::<xmlem::Document>().unwrap();
+    /// assert_eq!(doc.root().own_text(&doc), "Hello  world");
+    /// ```
+    pub fn own_text(self, document: &Document) -> String {
+        self.child_nodes(document)
+            .iter()
+            .filter_map(|x| match x {
+                Node::Text(t) => Some(t.as_str(document)),
+                Node::CDataSection(t) => Some(t.as_str(document)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Copy this element with its attributes and all of its descendants.
+    ///
+    /// The copy is detached; insert it with [`Element::append`] or similar.
+    ///
+    /// ```
+    /// let mut doc = r#"<root><a x="1">b</a></root>"#.parse::<xmlem::Document>().unwrap();
+    /// let a = doc.root().children(&doc)[0];
+    /// let copy = a.deep_clone(&mut doc);
+    /// doc.root().append(&mut doc, copy).unwrap();
+    /// assert_eq!(doc.to_string(), r#"<root><a x="1">b</a><a x="1">b</a></root>"#);
+    /// ```
+    pub fn deep_clone(self, document: &mut Document) -> Element {
+        match self.try_deep_clone(document) {
+            Ok(element) => element,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub fn try_deep_clone(self, document: &mut Document) -> Result<Element, Error> {
+        Ok(document
+            .clone_subtree(self.as_node())?
+            .as_element()
+            .unwrap())
+    }
+
+    /// Copy this element and its descendants into a new document, with the copy as the root.
+    ///
+    /// Namespace declarations in scope from ancestors are added to the new root, so the result is
+    /// namespace-well-formed on its own. The XML declaration is carried over too.
+    ///
+    /// ```
+    /// let doc = r#"<merge xmlns:latin="http://example.com"><Row><include latin:x="1"/></Row></merge>"#
+    ///     .parse::<xmlem::Document>()
+    ///     .unwrap();
+    /// let row = doc.root().children(&doc)[0];
+    /// let row_doc = row.to_document(&doc);
+    /// assert_eq!(
+    ///     row_doc.to_string(),
+    ///     r#"<Row xmlns:latin="http://example.com"><include latin:x="1"/></Row>"#
+    /// );
+    /// ```
+    pub fn to_document(self, document: &Document) -> Document {
+        let mut new_doc = Document::new(self.name(document));
+        let placeholder = new_doc.root();
+        let root = new_doc
+            .import_node(document, self.as_node())
+            .as_element()
             .unwrap();
-        match element.children.iter().position(|x| x == &node) {
-            Some(i) => {
-                element.children.remove(i);
+        new_doc.root_key = root;
+        new_doc.free_subtree(placeholder.0);
+        new_doc.decl = document.decl.clone();
+
+        let mut inherited = IndexMap::new();
+        let mut ancestor = self.parent(document);
+        while let Some(element) = ancestor {
+            for (name, value) in element.attributes(document).iter().rev() {
+                let is_declaration =
+                    name.namespace() == Some("xmlns") || name.prefixed_name() == "xmlns";
+                if is_declaration
+                    && !inherited.contains_key(name)
+                    && !self.attributes(document).contains_key(name)
+                {
+                    inherited.insert(name.clone(), value.clone());
+                }
             }
-            None => return,
+            ancestor = element.parent(document);
+        }
+
+        if !inherited.is_empty() {
+            // Outermost declarations first, followed by the element's own attributes.
+            inherited.reverse();
+            inherited.extend(self.attributes(document).clone());
+            new_doc.attrs.insert(root.0, inherited);
         }
-        document.parents.remove(node.as_key());
+
+        new_doc
+    }
+
+    pub fn remove_child(self, document: &mut Document, node: Node) {
+        self.value(document);
+        if node.as_key().belongs_to(document) && node.parent(document) == Some(self) {
+            node.detach(document);
+        }
+    }
+
+    /// Remove `node` from this element's children, failing if it is not a child.
+    pub fn try_remove_child(self, document: &mut Document, node: Node) -> Result<(), Error> {
+        self.child_position(document, node)?;
+        self.remove_child(document, node);
+        Ok(())
     }
 
     pub fn parent(self, document: &Document) -> Option<Element> {
-        document.parents.get(self.0).copied()
+        self.as_node().parent(document)
     }
 
     pub fn child_nodes(self, document: &Document) -> &[Node] {
-        let element = document.nodes.get(self.0).unwrap().as_element().unwrap();
+        let element = self.value(document);
         &element.children
     }
 
+    pub fn try_child_nodes(self, document: &Document) -> Result<&[Node], Error> {
+        Ok(&self.try_value(document)?.children)
+    }
+
     pub fn children(self, document: &Document) -> Vec<Element> {
-        let element = document.nodes.get(self.0).unwrap().as_element().unwrap();
+        let element = self.value(document);
         element
             .children
             .iter()
@@ -202,6 +883,68 @@ impl Element {
             .collect()
     }
 
+    pub fn try_children(self, document: &Document) -> Result<Vec<Element>, Error> {
+        Ok(self
+            .try_child_nodes(document)?
+            .iter()
+            .filter_map(|x| x.as_element())
+            .collect())
+    }
+
+    pub fn first_child(self, document: &Document) -> Option<Node> {
+        self.child_nodes(document).first().copied()
+    }
+
+    pub fn last_child(self, document: &Document) -> Option<Node> {
+        self.child_nodes(document).last().copied()
+    }
+
+    /// The number of child nodes of any type.
+    pub fn child_count(self, document: &Document) -> usize {
+        self.child_nodes(document).len()
+    }
+
+    /// The child elements, like [`children`](Element::children) but without collecting them.
+    pub fn child_elements(self, document: &Document) -> ChildElements<'_> {
+        Elements(self.child_nodes(document).iter().copied())
+    }
+
+    /// All elements below this one in document order, not including itself.
+    ///
+    /// ```
+    /// let doc = "<a><b><c/></b><d/></a>".parse::<xmlem::Document>().unwrap();
+    /// let names = doc.root().descendants(&doc).map(|e| e.name(&doc)).collect::<Vec<_>>();
+    /// assert_eq!(names, ["b", "c", "d"]);
+    /// ```
+    pub fn descendants(self, document: &Document) -> Descendants<'_> {
+        Elements(self.descendant_nodes(document))
+    }
+
+    /// All nodes below this one in document order, not including itself.
+    pub fn descendant_nodes(self, document: &Document) -> DescendantNodes<'_> {
+        self.as_node().descendant_nodes(document)
+    }
+
+    /// The elements containing this one, starting with its parent.
+    pub fn ancestors(self, document: &Document) -> Ancestors<'_> {
+        self.as_node().ancestors(document)
+    }
+
+    /// The sibling elements after this one, in document order.
+    pub fn following_siblings(self, document: &Document) -> FollowingSiblingElements<'_> {
+        Elements(self.as_node().following_siblings(document))
+    }
+
+    /// The sibling elements before this one, nearest first.
+    pub fn preceding_siblings(self, document: &Document) -> PrecedingSiblingElements<'_> {
+        Elements(self.as_node().preceding_siblings(document))
+    }
+
+    /// Walk this element and its descendants depth-first. See [`Node::traverse`].
+    pub fn traverse(self, document: &Document) -> Traverse<'_> {
+        self.as_node().traverse(document)
+    }
+
     /// Get the qualified name of this element, already parsed.
     ///
     /// ```
@@ -216,10 +959,14 @@ impl Element {
     /// assert_eq!(doc.root().qname(&doc).local_part(), "root");
     /// ```
     pub fn qname<'d>(&self, document: &'d Document) -> &'d QName {
-        let element = document.nodes.get(self.0).unwrap().as_element().unwrap();
+        let element = self.value(document);
         &element.name
     }
 
+    pub fn try_qname<'d>(&self, document: &'d Document) -> Result<&'d QName, Error> {
+        Ok(&self.try_value(document)?.name)
+    }
+
     /// Get the unparsed qualified name of this element.
     ///
     /// ```
@@ -235,6 +982,40 @@ impl Element {
         self.qname(document).prefixed_name()
     }
 
+    pub fn try_name<'d>(&self, document: &'d Document) -> Result<&'d str, Error> {
+        Ok(self.try_qname(document)?.prefixed_name())
+    }
+
+    /// Rename this element in place, keeping its attributes, children and handle.
+    ///
+    /// ```
+    /// # use qname::qname;
+    /// let mut doc = r#"<root><potato a="b"/></root>"#.parse::<xmlem::Document>().unwrap();
+    /// let potato = doc.root().children(&doc)[0];
+    /// potato.set_name(&mut doc, qname!("vegetable"));
+    /// assert_eq!(doc.to_string(), r#"<root><vegetable a="b"/></root>"#);
+    /// ```
+    pub fn set_name(self, document: &mut Document, name: QName) {
+        let element = self.value_mut(document);
+        element.name = name;
+    }
+
+    /// Rename this element in place from an unparsed qualified name.
+    ///
+    /// Fails without changing the element if `name` is not a valid qualified name.
+    ///
+    /// ```
+    /// let mut doc = "<root/>".parse::<xmlem::Document>().unwrap();
+    /// doc.root().try_set_name(&mut doc, "x:root").unwrap();
+    /// assert!(doc.root().try_set_name(&mut doc, "a b").is_err());
+    /// assert_eq!(doc.root().name(&doc), "x:root");
+    /// ```
+    pub fn try_set_name(self, document: &mut Document, name: &str) -> Result<(), Error> {
+        let name = parse_name(name)?;
+        self.try_value_mut(document)?.name = name;
+        Ok(())
+    }
+
     /// Get the prefix of this element’s name.
     ///
     /// ```
@@ -261,38 +1042,293 @@ impl Element {
     /// assert_eq!(attrs.next(), None);
     /// ```
     pub fn attributes<'d>(&self, document: &'d Document) -> &'d IndexMap<QName, String> {
-        match document.attrs.get(self.0) {
+        match self.try_attributes(document) {
+            Ok(attrs) => attrs,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub fn try_attributes<'d>(
+        &self,
+        document: &'d Document,
+    ) -> Result<&'d IndexMap<QName, String>, Error> {
+        self.try_value(document)?;
+        Ok(match document.attrs.get(self.0) {
             Some(x) => x,
             None => &EMPTY_INDEXMAP,
-        }
+        })
     }
 
+    /// Get the value of the attribute with the given qualified name.
+    ///
+    /// Returns `None` if there is no such attribute, including when `name` is not a valid
+    /// qualified name.
     pub fn attribute<'d>(&self, document: &'d Document, name: &str) -> Option<&'d str> {
-        let attrs = self.attributes(document);
+        match self.try_attribute(document, name) {
+            Ok(value) => value,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Like [`Element::attribute`], but fails if this element does not exist.
+    ///
+    /// ```
+    /// let mut doc = r#"<root><a x="1"/></root>"#.parse::<xmlem::Document>().unwrap();
+    /// let a = doc.root().children(&doc)[0];
+    /// assert_eq!(a.try_attribute(&doc, "x"), Ok(Some("1")));
+    /// doc.root().remove_child(&mut doc, a.as_node());
+    /// doc.compact([]);
+    /// assert!(a.try_attribute(&doc, "x").is_err());
+    /// assert!(a.try_set_attribute(&mut doc, "x", "2").is_err());
+    /// ```
+    pub fn try_attribute<'d>(
+        &self,
+        document: &'d Document,
+        name: &str,
+    ) -> Result<Option<&'d str>, Error> {
+        let attrs = self.try_attributes(document)?;
+        let name = match name.parse::<QName>() {
+            Ok(name) => name,
+            Err(_) => return Ok(None),
+        };
 
-        attrs.get(&name.parse::<QName>().unwrap()).map(|x| &**x)
+        Ok(attrs.get(&name).map(|x| &**x))
     }
 
+    /// Set the value of an attribute, adding it after any existing ones if it is new.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid qualified name or this element does not exist. Use
+    /// [`Element::try_set_attribute`] for a fallible alternative.
     pub fn set_attribute(&self, document: &mut Document, name: &str, value: &str) {
+        if let Err(e) = self.try_set_attribute(document, name, value) {
+            panic!("{e}");
+        }
+    }
+
+    /// Set the value of an attribute, failing if `name` is not a valid qualified name or this
+    /// element does not exist.
+    ///
+    /// ```
+    /// let mut doc = "<root/>".parse::<xmlem::Document>().unwrap();
+    /// doc.root().try_set_attribute(&mut doc, "a", "b").unwrap();
+    /// assert!(doc.root().try_set_attribute(&mut doc, "a b", "c").is_err());
+    /// assert_eq!(doc.to_string(), r#"<root a="b"/>"#);
+    /// ```
+    pub fn try_set_attribute(
+        &self,
+        document: &mut Document,
+        name: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        let name = parse_name(name)?;
+        self.try_value(document)?;
+
         if !document.attrs.contains_key(self.0) {
             document.attrs.insert(self.0, Default::default());
         }
 
         let attrs = document.attrs.get_mut(self.0).unwrap();
-        attrs.insert(name.parse().unwrap(), value.into());
+        attrs.insert(name, value.into());
+        Ok(())
     }
 
-    pub fn remove_attribute(&self, document: &mut Document, name: &str) {
-        if !document.attrs.contains_key(self.0) {
+    /// Parse the value of an attribute, or `None` if there is no such attribute.
+    ///
+    /// ```
+    /// let doc = r#"<Row x="200" keyWidth="8.18"/>"#.parse::<xmlem::Document>().unwrap();
+    /// let row = doc.root();
+    /// assert_eq!(row.attribute_as::<i32>(&doc, "x"), Ok(Some(200)));
+    /// assert_eq!(row.attribute_as::<f64>(&doc, "keyWidth"), Ok(Some(8.18)));
+    /// assert_eq!(row.attribute_as::<i32>(&doc, "y"), Ok(None));
+    /// assert!(row.attribute_as::<i32>(&doc, "keyWidth").is_err());
+    /// ```
+    pub fn attribute_as<T: FromStr>(
+        &self,
+        document: &Document,
+        name: &str,
+    ) -> Result<Option<T>, AttributeError<T::Err>> {
+        self.attribute_with(document, name, str::parse)
+    }
+
+    /// Parse a boolean attribute, accepting `true`, `false`, `1` and `0` like XML Schema's
+    /// `xs:boolean`. Returns `None` if there is no such attribute.
+    ///
+    /// ```
+    /// let doc = r#"<key repeat="1" sticky="false" wide="yes"/>"#.parse::<xmlem::Document>().unwrap();
+    /// let key = doc.root();
+    /// assert_eq!(key.attribute_bool(&doc, "repeat"), Ok(Some(true)));
+    /// assert_eq!(key.attribute_bool(&doc, "sticky"), Ok(Some(false)));
+    /// assert!(key.attribute_bool(&doc, "wide").is_err());
+    /// ```
+    pub fn attribute_bool(
+        &self,
+        document: &Document,
+        name: &str,
+    ) -> Result<Option<bool>, AttributeError<ParseBoolError>> {
+        self.attribute_with(document, name, |value| match value.trim() {
+            "1" => Ok(true),
+            "0" => Ok(false),
+            value => value.parse(),
+        })
+    }
+
+    fn attribute_with<T, E>(
+        &self,
+        document: &Document,
+        name: &str,
+        parse: impl FnOnce(&str) -> Result<T, E>,
+    ) -> Result<Option<T>, AttributeError<E>> {
+        let value = match self.attribute(document, name) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        parse(value).map(Some).map_err(|source| AttributeError {
+            element: self.name(document).to_string(),
+            attribute: name.to_string(),
+            value: value.to_string(),
+            source,
+        })
+    }
+
+    /// Set an attribute to the formatted value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid qualified name or this element does not exist. Use
+    /// [`Element::try_set_attribute`] for a fallible alternative.
+    ///
+    /// ```
+    /// let mut doc = "<Row/>".parse::<xmlem::Document>().unwrap();
+    /// doc.root().set_attribute_value(&mut doc, "x", 200);
+    /// doc.root().set_attribute_value(&mut doc, "repeat", true);
+    /// assert_eq!(doc.to_string(), r#"<Row x="200" repeat="true"/>"#);
+    /// ```
+    pub fn set_attribute_value(&self, document: &mut Document, name: &str, value: impl Display) {
+        self.set_attribute(document, name, &value.to_string());
+    }
+
+    /// The whitespace-separated tokens of an attribute, such as `class`. Empty if there is no such
+    /// attribute.
+    ///
+    /// ```
+    /// let doc = r#"<div class=" a  b "/>"#.parse::<xmlem::Document>().unwrap();
+    /// let classes = doc.root().attribute_tokens(&doc, "class").collect::<Vec<_>>();
+    /// assert_eq!(classes, ["a", "b"]);
+    /// ```
+    pub fn attribute_tokens<'d>(&self, document: &'d Document, name: &str) -> SplitWhitespace<'d> {
+        self.attribute(document, name)
+            .unwrap_or_default()
+            .split_whitespace()
+    }
+
+    pub fn has_attribute_token(&self, document: &Document, name: &str, token: &str) -> bool {
+        self.attribute_tokens(document, name).any(|x| x == token)
+    }
+
+    /// Add a token to a whitespace-separated attribute unless it is already present, creating the
+    /// attribute if needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid qualified name.
+    ///
+    /// ```
+    /// let mut doc = r#"<div class="a"/>"#.parse::<xmlem::Document>().unwrap();
+    /// let div = doc.root();
+    /// div.add_attribute_token(&mut doc, "class", "b");
+    /// div.add_attribute_token(&mut doc, "class", "a");
+    /// div.remove_attribute_token(&mut doc, "class", "a");
+    /// assert_eq!(doc.to_string(), r#"<div class="b"/>"#);
+    /// ```
+    pub fn add_attribute_token(&self, document: &mut Document, name: &str, token: &str) {
+        if self.has_attribute_token(document, name, token) {
             return;
         }
+        let value = self
+            .attribute_tokens(document, name)
+            .chain([token])
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.set_attribute(document, name, &value);
+    }
 
-        let attrs = document.attrs.get_mut(self.0).unwrap();
-        attrs.shift_remove(&name.parse::<QName>().unwrap());
+    /// Remove every occurrence of a token from a whitespace-separated attribute. The attribute is
+    /// kept, even if it becomes empty.
+    pub fn remove_attribute_token(&self, document: &mut Document, name: &str, token: &str) {
+        if !self.has_attribute_token(document, name, token) {
+            return;
+        }
+        let value = self
+            .attribute_tokens(document, name)
+            .filter(|x| *x != token)
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.set_attribute(document, name, &value);
+    }
+
+    /// Remove the attribute with the given qualified name, if present.
+    pub fn remove_attribute(&self, document: &mut Document, name: &str) {
+        if let Err(e) = self.try_remove_attribute(document, name) {
+            panic!("{e}");
+        }
+    }
+
+    /// Like [`Element::remove_attribute`], but fails if this element does not exist.
+    pub fn try_remove_attribute(&self, document: &mut Document, name: &str) -> Result<(), Error> {
+        self.try_value(document)?;
+        let name = match name.parse::<QName>() {
+            Ok(name) => name,
+            Err(_) => return Ok(()),
+        };
+
+        if let Some(attrs) = document.attrs.get_mut(self.0) {
+            attrs.shift_remove(&name);
+        }
+        Ok(())
+    }
+
+    /// A location path from the root element to this one, such as `/merge/include[2]`, which
+    /// [`Document::resolve_path`] turns back into this element.
+    ///
+    /// Steps are qualified names, with a 1-based position among same-named siblings when there
+    /// are several. Positions change when siblings are inserted or removed; see
+    /// [`Element::path_with_keys`] for paths that survive such edits. A detached element, or one
+    /// in a detached subtree, has no path.
+    ///
+    /// ```
+    /// let doc = "<merge><include/><include/><Row/></merge>".parse::<xmlem::Document>().unwrap();
+    /// let second = doc.root().children(&doc)[1];
+    /// assert_eq!(second.path(&doc).unwrap(), "/merge/include[2]");
+    /// assert_eq!(doc.resolve_path("/merge/include[2]"), Some(second));
+    /// assert_eq!(doc.root().children(&doc)[2].path(&doc).unwrap(), "/merge/Row");
+    /// ```
+    pub fn path(self, document: &Document) -> Option<String> {
+        self.path_with_keys(document, &[])
+    }
+
+    /// Like [`Element::path`], but each step is identified by the first of the `keys` attributes
+    /// whose value is unique among same-named siblings, if there is one.
+    ///
+    /// ```
+    /// let doc = r#"<resources><string name="a"/><string name="b" id="x"/></resources>"#
+    ///     .parse::<xmlem::Document>()
+    ///     .unwrap();
+    /// let b = doc.root().children(&doc)[1];
+    /// assert_eq!(
+    ///     b.path_with_keys(&doc, &["id", "name"]).unwrap(),
+    ///     "/resources/string[@id='x']"
+    /// );
+    /// assert_eq!(doc.resolve_path("/resources/string[@name='b']"), Some(b));
+    /// ```
+    pub fn path_with_keys(self, document: &Document, keys: &[&str]) -> Option<String> {
+        self.value(document);
+        path::element_path(document, self, keys)
     }
 
     pub fn display(&self, document: &Document) -> String {
-        let element = document.nodes.get(self.0).unwrap().as_element().unwrap();
+        let element = self.value(document);
         let mut s = Vec::<u8>::new();
         element
             .print(
@@ -305,89 +1341,62 @@ impl Element {
     }
 
     pub fn walk<'d>(&self, doc: &'d Document) -> Box<dyn Iterator<Item = Element> + 'd> {
-        walk_tree(doc, *self)
+        Box::new(self.descendants(doc))
     }
 
     pub fn next_sibling_element(&self, doc: &Document) -> Option<Element> {
-        let parent = match self.parent(doc) {
-            Some(v) => v,
-            None => return None,
-        };
-
-        let children = parent.children(doc);
-        let mut index = children
-            .iter()
-            .position(|x| x == self)
-            .expect("element has to be child of parent");
-        index += 1;
-
-        if index < children.len() {
-            return Some(children[index]);
-        }
-
-        None
+        self.following_siblings(doc).next()
     }
 
     pub fn prev_sibling_element(&self, doc: &Document) -> Option<Element> {
-        let parent = match self.parent(doc) {
-            Some(v) => v,
-            None => return None,
-        };
-
-        let children = parent.children(doc);
-        let mut index = children
-            .iter()
-            .position(|x| x == self)
-            .expect("element has to be child of parent");
-
-        if index == 0 {
-            return None;
-        }
-        index -= 1;
+        self.preceding_siblings(doc).next()
+    }
 
-        if index == 0 {
-            return None;
-        }
+    /// Returns whether this element matches `selector`. `:scope` in the selector refers to this
+    /// element.
+    pub fn matches(&self, doc: &Document, selector: &Selector) -> bool {
+        selector.matches_in_scope(doc, *self, Some(*self))
+    }
 
-        Some(children[index])
+    /// The nearest of this element and its ancestors matching `selector`. `:scope` in the
+    /// selector refers to this element.
+    ///
+    /// ```
+    /// use xmlem::{Document, Selector};
+    ///
+    /// let doc = r#"<resources><plurals name="a"><item>1</item></plurals></resources>"#
+    ///     .parse::<Document>()
+    ///     .unwrap();
+    /// let item = doc.root().query_selector(&doc, &Selector::new("item").unwrap()).unwrap();
+    /// let plurals = item.closest(&doc, &Selector::new("[name]").unwrap()).unwrap();
+    /// assert_eq!(plurals.attribute(&doc, "name"), Some("a"));
+    /// assert_eq!(item.closest(&doc, &Selector::new("item").unwrap()), Some(item));
+    /// ```
+    pub fn closest(&self, doc: &Document, selector: &Selector) -> Option<Element> {
+        let mut caches = MatchingCaches::default();
+        std::iter::once(*self)
+            .chain(self.ancestors(doc))
+            .find(|x| selector.matches_with(doc, *x, Some(*self), &mut caches))
     }
 
+    /// The first descendant matching `selector`, in document order. `:scope` in the selector
+    /// refers to this element.
     pub fn query_selector(&self, doc: &Document, selector: &Selector) -> Option<Element> {
-        self.walk(doc).find(|x| selector.matches(doc, *x))
+        self.query_selector_iter(doc, selector).next()
     }
 
     pub fn query_selector_all(&self, doc: &Document, selector: &Selector) -> Vec<Element> {
-        self.walk(doc)
-            .filter(|x| selector.matches(doc, *x))
-            .collect()
+        self.query_selector_iter(doc, selector).collect()
     }
-}
 
-fn walk_tree<'a>(doc: &'a Document, element: Element) -> Box<dyn Iterator<Item = Element> + 'a> {
-    let children = element.children(doc).to_vec();
-    let mut index = 0usize;
-
-    let mut last_child: Option<Box<dyn Iterator<Item = Element>>> = None;
-
-    Box::new(std::iter::from_fn(move || {
-        if let Some(iter) = last_child.as_mut() {
-            if let Some(next) = iter.next() {
-                return Some(next);
-            } else {
-                last_child = None;
-            }
-        }
-
-        if index >= children.len() {
-            return None;
-        }
-
-        let child = children[index];
-        index += 1;
-
-        last_child = Some(Box::new(walk_tree(doc, child)));
-        Some(child)
-    }))
+    /// The descendants matching `selector`, lazily and in document order.
+    pub fn query_selector_iter<'d>(
+        &self,
+        doc: &'d Document,
+        selector: &'d Selector,
+    ) -> QuerySelectorIter<'d> {
+        QuerySelectorIter::new(doc, selector, *self, false)
+    }
 }
 
 static EMPTY_INDEXMAP: Lazy<IndexMap<QName, String>> = Lazy::new(IndexMap::new);
@@ -0,0 +1,97 @@
+use std::fmt;
+
+use qname::QName;
+
+use crate::key::Node;
+
+/// Error returned by fallible operations on a [`Document`](crate::Document) and its nodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The name is not a valid qualified name.
+    InvalidName(String),
+    /// The node does not exist in the document, because it was deleted or compacted away.
+    NodeNotFound(Node),
+    /// The handle belongs to another document. Only detected in debug builds or with the
+    /// `checked-handles` feature.
+    WrongDocument(Node),
+    /// The node is of a different type than the operation requires, such as an element handle
+    /// that refers to a text node of another document.
+    WrongNodeType(Node),
+    /// The reference node is not a child of the element.
+    NotAChild(Node),
+    /// The index is past the end of the element's children.
+    OutOfBounds { index: usize, len: usize },
+    /// The node is not attached to the document.
+    Detached(Node),
+    /// The node cannot be a child here, because it is the root, a document type, or an ancestor
+    /// of the element.
+    Hierarchy(Node),
+    /// The text cannot be the content of a comment, because it contains `--` or ends with `-`.
+    InvalidComment(String),
+    /// The query given to [`Query::has`](crate::Query::has) uses `has` itself, which selectors
+    /// do not allow.
+    NestedHas(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidName(name) => write!(f, "Invalid qualified name: {name:?}"),
+            Error::NodeNotFound(node) => write!(f, "Node not found in document: {node:?}"),
+            Error::WrongDocument(node) => write!(f, "Node belongs to another document: {node:?}"),
+            Error::WrongNodeType(node) => write!(f, "Wrong node type: {node:?}"),
+            Error::NotAChild(node) => write!(f, "Reference node is not a child: {node:?}"),
+            Error::OutOfBounds { index, len } => {
+                write!(f, "Index {index} out of bounds for {len} children")
+            }
+            Error::Detached(node) => write!(f, "Node is not attached: {node:?}"),
+            Error::Hierarchy(node) => write!(f, "Node cannot be inserted here: {node:?}"),
+            Error::InvalidComment(text) => write!(f, "Invalid comment text: {text:?}"),
+            Error::NestedHas(query) => write!(f, "Query cannot be nested in :has(): {query}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Error returned when an attribute value cannot be parsed as the requested type, naming the
+/// element and attribute it came from.
+///
+/// ```
+/// let doc = r#"<Row x="2OO"/>"#.parse::<xmlem::Document>().unwrap();
+/// let err = doc.root().attribute_as::<u32>(&doc, "x").unwrap_err();
+/// assert_eq!(
+///     err.to_string(),
+///     r#"Invalid value "2OO" for attribute x of element <Row>: invalid digit found in string"#
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeError<E> {
+    pub element: String,
+    pub attribute: String,
+    pub value: String,
+    /// The error returned by the parser.
+    pub source: E,
+}
+
+impl<E: fmt::Display> fmt::Display for AttributeError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid value {:?} for attribute {} of element <{}>: {}",
+            self.value, self.attribute, self.element, self.source
+        )
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for AttributeError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+pub(crate) fn parse_name(name: &str) -> Result<QName, Error> {
+    name.parse()
+        .map_err(|_| Error::InvalidName(name.to_string()))
+}
@@ -0,0 +1,266 @@
+//! Lazy iterators for navigating a [`Document`].
+//!
+//! Apart from selector matching, none of these iterators allocate; they follow parent and sibling
+//! links as they go. The tree must not be modified while iterating, which the borrow of the
+//! document enforces.
+
+use std::{iter::Copied, slice};
+
+use crate::{element::Element, key::Node, select::MatchingCaches, Document, Selector};
+
+/// Adapts an iterator over nodes into one over the elements among them.
+#[derive(Debug, Clone)]
+pub struct Elements<I>(pub(crate) I);
+
+impl<I: Iterator<Item = Node>> Iterator for Elements<I> {
+    type Item = Element;
+
+    fn next(&mut self) -> Option<Element> {
+        self.0.by_ref().find_map(Node::as_element)
+    }
+}
+
+/// The element children of an element. See [`Element::child_elements`].
+pub type ChildElements<'d> = Elements<Copied<slice::Iter<'d, Node>>>;
+
+/// The descendant elements of a node, in document order. See [`Element::descendants`].
+pub type Descendants<'d> = Elements<DescendantNodes<'d>>;
+
+/// The element siblings after an element. See [`Element::following_siblings`].
+pub type FollowingSiblingElements<'d> = Elements<FollowingSiblings<'d>>;
+
+/// The element siblings before an element, nearest first. See
+/// [`Element::preceding_siblings`].
+pub type PrecedingSiblingElements<'d> = Elements<PrecedingSiblings<'d>>;
+
+/// All descendants of a node, in document order. See [`Node::descendant_nodes`].
+#[derive(Debug, Clone)]
+pub struct DescendantNodes<'d> {
+    doc: &'d Document,
+    root: Node,
+    next: Option<Node>,
+}
+
+impl<'d> DescendantNodes<'d> {
+    pub(crate) fn new(doc: &'d Document, root: Node) -> Self {
+        DescendantNodes {
+            doc,
+            root,
+            next: first_child(doc, root),
+        }
+    }
+}
+
+impl Iterator for DescendantNodes<'_> {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        let current = self.next?;
+
+        self.next = first_child(self.doc, current).or_else(|| {
+            let mut node = current;
+            loop {
+                if node == self.root {
+                    return None;
+                }
+                if let Some(sibling) = node.next_sibling(self.doc) {
+                    return Some(sibling);
+                }
+                node = Node::Element(node.parent(self.doc)?);
+            }
+        });
+
+        Some(current)
+    }
+}
+
+/// The ancestors of a node, nearest first. See [`Node::ancestors`].
+#[derive(Debug, Clone)]
+pub struct Ancestors<'d> {
+    doc: &'d Document,
+    next: Option<Element>,
+}
+
+impl<'d> Ancestors<'d> {
+    pub(crate) fn new(doc: &'d Document, node: Node) -> Self {
+        Ancestors {
+            doc,
+            next: node.parent(doc),
+        }
+    }
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = Element;
+
+    fn next(&mut self) -> Option<Element> {
+        let current = self.next?;
+        self.next = current.parent(self.doc);
+        Some(current)
+    }
+}
+
+/// The siblings after a node. See [`Node::following_siblings`].
+#[derive(Debug, Clone)]
+pub struct FollowingSiblings<'d> {
+    doc: &'d Document,
+    next: Option<Node>,
+}
+
+impl<'d> FollowingSiblings<'d> {
+    pub(crate) fn new(doc: &'d Document, node: Node) -> Self {
+        FollowingSiblings {
+            doc,
+            next: node.next_sibling(doc),
+        }
+    }
+}
+
+impl Iterator for FollowingSiblings<'_> {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        let current = self.next?;
+        self.next = current.next_sibling(self.doc);
+        Some(current)
+    }
+}
+
+/// The siblings before a node, nearest first. See [`Node::preceding_siblings`].
+#[derive(Debug, Clone)]
+pub struct PrecedingSiblings<'d> {
+    doc: &'d Document,
+    next: Option<Node>,
+}
+
+impl<'d> PrecedingSiblings<'d> {
+    pub(crate) fn new(doc: &'d Document, node: Node) -> Self {
+        PrecedingSiblings {
+            doc,
+            next: node.prev_sibling(doc),
+        }
+    }
+}
+
+impl Iterator for PrecedingSiblings<'_> {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        let current = self.next?;
+        self.next = current.prev_sibling(self.doc);
+        Some(current)
+    }
+}
+
+/// The elements of a subtree matching a selector, in document order. See
+/// [`Element::query_selector_iter`] and [`Document::query_selector_iter`].
+///
+/// Matching shares caches across the subtree, so `:nth-*` pseudo-classes and descendant
+/// combinators cost about the same per element whatever the size of the tree.
+#[derive(Debug)]
+pub struct QuerySelectorIter<'d> {
+    doc: &'d Document,
+    selector: &'d Selector,
+    scope: Element,
+    inclusive: bool,
+    traverse: Traverse<'d>,
+    caches: MatchingCaches,
+}
+
+impl<'d> QuerySelectorIter<'d> {
+    /// Matches the descendants of `scope`, and `scope` itself first if `inclusive`.
+    pub(crate) fn new(
+        doc: &'d Document,
+        selector: &'d Selector,
+        scope: Element,
+        inclusive: bool,
+    ) -> Self {
+        QuerySelectorIter {
+            doc,
+            selector,
+            scope,
+            inclusive,
+            traverse: scope.traverse(doc),
+            caches: MatchingCaches::for_subtree(doc, scope, selector),
+        }
+    }
+}
+
+impl Iterator for QuerySelectorIter<'_> {
+    type Item = Element;
+
+    fn next(&mut self) -> Option<Element> {
+        for edge in self.traverse.by_ref() {
+            let found = match edge {
+                NodeEdge::Enter(Node::Element(e)) if e != self.scope || self.inclusive => {
+                    let scope = Some(self.scope);
+                    self.selector
+                        .matches_with(self.doc, e, scope, &mut self.caches)
+                        .then_some(e)
+                }
+                _ => None,
+            };
+            self.caches.visit(self.doc, edge);
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+}
+
+/// An event of a depth-first traversal. See [`Node::traverse`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeEdge {
+    /// The traversal reached the node, before any of its descendants.
+    Enter(Node),
+    /// The traversal is done with the node and all of its descendants.
+    Leave(Node),
+}
+
+/// A depth-first traversal yielding an enter and a leave event for every node of a subtree,
+/// including its root. See [`Node::traverse`].
+#[derive(Debug, Clone)]
+pub struct Traverse<'d> {
+    doc: &'d Document,
+    root: Node,
+    next: Option<NodeEdge>,
+}
+
+impl<'d> Traverse<'d> {
+    pub(crate) fn new(doc: &'d Document, root: Node) -> Self {
+        Traverse {
+            doc,
+            root,
+            next: Some(NodeEdge::Enter(root)),
+        }
+    }
+}
+
+impl Iterator for Traverse<'_> {
+    type Item = NodeEdge;
+
+    fn next(&mut self) -> Option<NodeEdge> {
+        let current = self.next?;
+
+        self.next = match current {
+            NodeEdge::Enter(node) => match first_child(self.doc, node) {
+                Some(child) => Some(NodeEdge::Enter(child)),
+                None => Some(NodeEdge::Leave(node)),
+            },
+            NodeEdge::Leave(node) if node == self.root => None,
+            NodeEdge::Leave(node) => match node.next_sibling(self.doc) {
+                Some(sibling) => Some(NodeEdge::Enter(sibling)),
+                None => node
+                    .parent(self.doc)
+                    .map(|parent| NodeEdge::Leave(Node::Element(parent))),
+            },
+        };
+
+        Some(current)
+    }
+}
+
+fn first_child(doc: &Document, node: Node) -> Option<Node> {
+    node.as_element()?.first_child(doc)
+}
@@ -1,9 +1,116 @@
-use slotmap::new_key_type;
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+};
 
-use crate::{element::Element, Document};
+use slotmap::{Key, KeyData};
 
-new_key_type! {
-    pub(crate) struct DocKey;
+use crate::{
+    element::Element,
+    error::Error,
+    iter::{Ancestors, DescendantNodes, FollowingSiblings, PrecedingSiblings, Traverse},
+    value::NodeValue,
+    Document,
+};
+
+/// Identifies the document a handle was created by, so that handles used with another document
+/// can be detected.
+///
+/// Only tracked in debug builds or with the `checked-handles` feature; otherwise this is empty
+/// and all documents compare equal.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct DocId {
+    #[cfg(any(debug_assertions, feature = "checked-handles"))]
+    id: u32,
+}
+
+impl DocId {
+    pub(crate) fn next() -> Self {
+        #[cfg(any(debug_assertions, feature = "checked-handles"))]
+        {
+            use std::sync::atomic::{self, AtomicU32};
+
+            static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+            DocId {
+                id: NEXT_ID.fetch_add(1, atomic::Ordering::Relaxed),
+            }
+        }
+
+        #[cfg(not(any(debug_assertions, feature = "checked-handles")))]
+        DocId {}
+    }
+}
+
+/// Key of a node in a document's storage, tagged with the [`DocId`] of that document.
+///
+/// The slot maps only look at the key data, so keys they hand out themselves are untagged; all
+/// keys that end up in handles are tagged by [`NodeMap::insert`](crate::document::NodeMap).
+/// Comparison and hashing ignore the tag, as [`Key`] requires.
+#[derive(Default, Clone, Copy)]
+pub(crate) struct DocKey {
+    data: KeyData,
+    doc: DocId,
+}
+
+impl DocKey {
+    pub(crate) fn tagged(self, doc: DocId) -> Self {
+        DocKey { doc, ..self }
+    }
+
+    pub(crate) fn belongs_to(self, doc: &Document) -> bool {
+        self.doc == doc.nodes.id
+    }
+}
+
+impl From<KeyData> for DocKey {
+    fn from(data: KeyData) -> Self {
+        DocKey {
+            data,
+            doc: DocId::default(),
+        }
+    }
+}
+
+impl PartialEq for DocKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+    }
+}
+
+impl Eq for DocKey {}
+
+impl PartialOrd for DocKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DocKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.data.cmp(&other.data)
+    }
+}
+
+impl Hash for DocKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.data.hash(state);
+    }
+}
+
+// SAFETY: `data` returns the key data the key was created from, and `From<KeyData>` keeps it
+// unchanged. `Default` has the null key data, and `PartialEq`, `Eq`, `PartialOrd`, `Ord` and
+// `Hash` look at the key data alone, so the key behaves exactly like its `KeyData`.
+unsafe impl Key for DocKey {
+    fn data(&self) -> KeyData {
+        self.data
+    }
+}
+
+impl fmt::Debug for DocKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DocKey({:?})", self.data)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -11,8 +118,62 @@ pub struct Text(pub(crate) DocKey);
 
 impl Text {
     pub fn as_str<'d>(&self, doc: &'d Document) -> &'d str {
-        let node_value = &doc.nodes[self.0];
-        node_value.as_str().unwrap()
+        match self.try_as_str(doc) {
+            Ok(text) => text,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub fn try_as_str<'d>(&self, doc: &'d Document) -> Result<&'d str, Error> {
+        Node::Text(*self).check(doc)?;
+        Ok(doc.nodes[self.0].as_str().unwrap())
+    }
+
+    pub fn set(&self, doc: &mut Document, text: &str) {
+        if let Err(e) = self.try_set(doc, text) {
+            panic!("{e}");
+        }
+    }
+
+    pub fn try_set(&self, doc: &mut Document, text: &str) -> Result<(), Error> {
+        Node::Text(*self).check(doc)?;
+        *doc.nodes[self.0].as_string_mut().unwrap() = text.to_string();
+        Ok(())
+    }
+
+    /// Split this text node in two at the given byte offset, like the DOM's `Text.splitText()`.
+    ///
+    /// This node keeps the text before `offset`. The remainder is moved into a new text node,
+    /// which is inserted as the next sibling if this node is attached, and returned.
+    ///
+    /// ```
+    /// let mut doc = "<root>Hello world</root>".parse::<xmlem::Document>().unwrap();
+    /// let hello = doc.root().child_nodes(&doc)[0].as_text().unwrap();
+    /// let world = hello.split_at(&mut doc, 6);
+    /// assert_eq!(hello.as_str(&doc), "Hello ");
+    /// assert_eq!(world.as_str(&doc), "world");
+    /// assert_eq!(doc.root().child_nodes(&doc).len(), 2);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is not on a `char` boundary, or is past the end of the text.
+    pub fn split_at(self, doc: &mut Document, offset: usize) -> Text {
+        Node::Text(self).assert_belongs_to(doc);
+        let tail = doc.nodes[self.0].as_string_mut().unwrap().split_off(offset);
+        let new_text = doc.create_text(&tail);
+        insert_sibling_after(doc, Node::Text(self), Node::Text(new_text));
+        new_text
+    }
+
+    /// Convert this text node into a CDATA section with the same content, keeping its position.
+    pub fn into_cdata(self, doc: &mut Document) -> CDataSection {
+        Node::Text(self).assert_belongs_to(doc);
+        let text = std::mem::take(doc.nodes[self.0].as_string_mut().unwrap());
+        doc.nodes[self.0] = NodeValue::CData(text);
+        let cdata = CDataSection(self.0);
+        replace_in_container(doc, Node::Text(self), Node::CDataSection(cdata));
+        cdata
     }
 }
 
@@ -21,8 +182,39 @@ pub struct CDataSection(pub(crate) DocKey);
 
 impl CDataSection {
     pub fn as_str<'d>(&self, doc: &'d Document) -> &'d str {
-        let node_value = &doc.nodes[self.0];
-        node_value.as_str().unwrap()
+        match self.try_as_str(doc) {
+            Ok(text) => text,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub fn try_as_str<'d>(&self, doc: &'d Document) -> Result<&'d str, Error> {
+        Node::CDataSection(*self).check(doc)?;
+        Ok(doc.nodes[self.0].as_str().unwrap())
+    }
+
+    /// Set the content of this CDATA section. Content containing `]]>` is written as two
+    /// sections split inside the terminator, so it still reads back the same.
+    pub fn set(&self, doc: &mut Document, text: &str) {
+        if let Err(e) = self.try_set(doc, text) {
+            panic!("{e}");
+        }
+    }
+
+    pub fn try_set(&self, doc: &mut Document, text: &str) -> Result<(), Error> {
+        Node::CDataSection(*self).check(doc)?;
+        *doc.nodes[self.0].as_string_mut().unwrap() = text.to_string();
+        Ok(())
+    }
+
+    /// Convert this CDATA section into a text node with the same content, keeping its position.
+    pub fn into_text(self, doc: &mut Document) -> Text {
+        Node::CDataSection(self).assert_belongs_to(doc);
+        let text = std::mem::take(doc.nodes[self.0].as_string_mut().unwrap());
+        doc.nodes[self.0] = NodeValue::Text(text);
+        let new_text = Text(self.0);
+        replace_in_container(doc, Node::CDataSection(self), Node::Text(new_text));
+        new_text
     }
 }
 
@@ -41,14 +233,71 @@ pub struct Comment(pub(crate) DocKey);
 
 impl Comment {
     pub fn as_str<'d>(&self, doc: &'d Document) -> &'d str {
-        let node_value = &doc.nodes[self.0];
-        node_value.as_str().unwrap()
+        match self.try_as_str(doc) {
+            Ok(text) => text,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub fn try_as_str<'d>(&self, doc: &'d Document) -> Result<&'d str, Error> {
+        Node::Comment(*self).check(doc)?;
+        Ok(doc.nodes[self.0].as_str().unwrap())
+    }
+
+    /// Set the content of this comment.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the text contains `--` or ends with `-`, which a comment cannot hold. Use
+    /// [`Comment::try_set`] to handle this as an error.
+    pub fn set(&self, doc: &mut Document, text: &str) {
+        if let Err(e) = self.try_set(doc, text) {
+            panic!("{e}");
+        }
+    }
+
+    /// Set the content of this comment, or return [`Error::InvalidComment`] if the text contains
+    /// `--` or ends with `-`.
+    ///
+    /// ```
+    /// let mut doc = "<root><!--a--></root>".parse::<xmlem::Document>().unwrap();
+    /// let comment = doc.root().child_nodes(&doc)[0].as_comment().unwrap();
+    /// assert!(comment.try_set(&mut doc, "a--b").is_err());
+    /// assert!(comment.try_set(&mut doc, "a-").is_err());
+    /// comment.try_set(&mut doc, "a-b").unwrap();
+    /// assert_eq!(doc.to_string(), "<root><!--a-b--></root>");
+    /// ```
+    pub fn try_set(&self, doc: &mut Document, text: &str) -> Result<(), Error> {
+        Node::Comment(*self).check(doc)?;
+        if text.contains("--") || text.ends_with('-') {
+            return Err(Error::InvalidComment(text.to_string()));
+        }
+        *doc.nodes[self.0].as_string_mut().unwrap() = text.to_string();
+        Ok(())
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub struct DocumentType(pub(crate) DocKey);
 
+impl From<Text> for Node {
+    fn from(n: Text) -> Self {
+        Node::Text(n)
+    }
+}
+
+impl From<CDataSection> for Node {
+    fn from(n: CDataSection) -> Self {
+        Node::CDataSection(n)
+    }
+}
+
+impl From<Comment> for Node {
+    fn from(n: Comment) -> Self {
+        Node::Comment(n)
+    }
+}
+
 #[derive(Debug, Copy, PartialEq, Eq, Clone)]
 pub enum Node {
     Element(Element),
@@ -113,6 +362,255 @@ impl Node {
         }
     }
 
+    /// Panics if this node is a handle of another document.
+    pub(crate) fn assert_belongs_to(self, doc: &Document) {
+        if !self.as_key().belongs_to(doc) {
+            panic!("{}", Error::WrongDocument(self));
+        }
+    }
+
+    /// Ensures that the node exists in the document and its value is of the handle's type.
+    pub(crate) fn check(self, doc: &Document) -> Result<(), Error> {
+        if !self.as_key().belongs_to(doc) {
+            return Err(Error::WrongDocument(self));
+        }
+
+        let is_same_type = match (self, doc.nodes.get(self.as_key())) {
+            (_, None) => return Err(Error::NodeNotFound(self)),
+            (Node::Element(_), Some(NodeValue::Element(_)))
+            | (Node::Text(_), Some(NodeValue::Text(_)))
+            | (Node::CDataSection(_), Some(NodeValue::CData(_)))
+            | (Node::Comment(_), Some(NodeValue::Comment(_)))
+            | (Node::DocumentType(_), Some(NodeValue::DocumentType(_))) => true,
+            _ => false,
+        };
+
+        if is_same_type {
+            Ok(())
+        } else {
+            Err(Error::WrongNodeType(self))
+        }
+    }
+
+    /// The element this node is a child of.
+    ///
+    /// Nodes before or after the root element, the root itself and detached nodes have no parent.
+    pub fn parent(self, doc: &Document) -> Option<Element> {
+        self.assert_belongs_to(doc);
+        doc.parents.get(self.as_key()).copied()
+    }
+
+    /// The position of this node among its siblings.
+    ///
+    /// For nodes at the top level of the document (the prolog, the root element and anything
+    /// following it) this is the position in that sequence. Detached nodes have no index.
+    ///
+    /// ```
+    /// let doc = "<!-- a --><root>b<c/></root><!-- d -->".parse::<xmlem::Document>().unwrap();
+    /// let c = doc.root().child_nodes(&doc)[1];
+    /// assert_eq!(c.index_in_parent(&doc), Some(1));
+    /// assert_eq!(doc.root().as_node().index_in_parent(&doc), Some(1));
+    /// ```
+    pub fn index_in_parent(self, doc: &Document) -> Option<usize> {
+        match self.parent(doc) {
+            Some(parent) => {
+                let index = doc.positions.get(self.as_key()).copied();
+                debug_assert_eq!(
+                    index.and_then(|i| parent.child_nodes(doc).get(i)),
+                    Some(&self),
+                    "stale position for {self:?}"
+                );
+                index
+            }
+            None => top_level_index(doc, self),
+        }
+    }
+
+    pub fn next_sibling(self, doc: &Document) -> Option<Node> {
+        let index = self.index_in_parent(doc)? + 1;
+        match self.parent(doc) {
+            Some(parent) => parent.child_nodes(doc).get(index).copied(),
+            None => top_level_node(doc, index),
+        }
+    }
+
+    pub fn prev_sibling(self, doc: &Document) -> Option<Node> {
+        let index = self.index_in_parent(doc)?.checked_sub(1)?;
+        match self.parent(doc) {
+            Some(parent) => parent.child_nodes(doc).get(index).copied(),
+            None => top_level_node(doc, index),
+        }
+    }
+
+    /// The elements containing this node, starting with its parent.
+    ///
+    /// ```
+    /// let doc = "<a><b><c/></b></a>".parse::<xmlem::Document>().unwrap();
+    /// let c = doc.root().descendants(&doc).last().unwrap();
+    /// let names = c.as_node().ancestors(&doc).map(|e| e.name(&doc)).collect::<Vec<_>>();
+    /// assert_eq!(names, ["b", "a"]);
+    /// ```
+    pub fn ancestors(self, doc: &Document) -> Ancestors<'_> {
+        Ancestors::new(doc, self)
+    }
+
+    /// The siblings after this node, in document order.
+    ///
+    /// At the top level of the document these are the nodes following it in the prolog, root
+    /// element and epilogue sequence.
+    pub fn following_siblings(self, doc: &Document) -> FollowingSiblings<'_> {
+        FollowingSiblings::new(doc, self)
+    }
+
+    /// The siblings before this node, nearest first.
+    pub fn preceding_siblings(self, doc: &Document) -> PrecedingSiblings<'_> {
+        PrecedingSiblings::new(doc, self)
+    }
+
+    /// All nodes below this one in document order, not including itself. Only elements have
+    /// descendants.
+    pub fn descendant_nodes(self, doc: &Document) -> DescendantNodes<'_> {
+        self.assert_belongs_to(doc);
+        DescendantNodes::new(doc, self)
+    }
+
+    /// Walk this node and everything below it depth-first, yielding an [`Enter`] event before a
+    /// node's descendants and a [`Leave`] event after them. Nodes without children are entered
+    /// and left immediately.
+    ///
+    /// [`Enter`]: crate::iter::NodeEdge::Enter
+    /// [`Leave`]: crate::iter::NodeEdge::Leave
+    ///
+    /// ```
+    /// use xmlem::iter::NodeEdge;
+    ///
+    /// let doc = "<a>x<b/></a>".parse::<xmlem::Document>().unwrap();
+    /// let mut xml = String::new();
+    /// for edge in doc.root().as_node().traverse(&doc) {
+    ///     match edge {
+    ///         NodeEdge::Enter(node) => match node.as_element() {
+    ///             Some(e) => xml += &format!("<{}>", e.name(&doc)),
+    ///             None => xml += node.as_text().unwrap().as_str(&doc),
+    ///         },
+    ///         NodeEdge::Leave(node) => {
+    ///             if let Some(e) = node.as_element() {
+    ///                 xml += &format!("</{}>", e.name(&doc));
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    /// assert_eq!(xml, "<a>x<b></b></a>");
+    /// ```
+    pub fn traverse(self, doc: &Document) -> Traverse<'_> {
+        self.assert_belongs_to(doc);
+        Traverse::new(doc, self)
+    }
+
+    /// Unlink this node from its parent, or from the top level of the document.
+    ///
+    /// The node and its descendants remain valid and can be inserted elsewhere. The root element
+    /// cannot be detached; doing so does nothing. Returns whether the node was attached.
+    pub fn detach(self, doc: &mut Document) -> bool {
+        if self == Node::Element(doc.root()) {
+            return false;
+        }
+        let index = match self.index_in_parent(doc) {
+            Some(index) => index,
+            None => return false,
+        };
+        let parent = self.parent(doc);
+
+        match parent {
+            Some(parent) => {
+                parent.children_mut(doc).remove(index);
+                doc.parents.remove(self.as_key());
+            }
+            None if index < doc.before.len() => {
+                doc.before.remove(index);
+            }
+            None => {
+                doc.after.remove(index - doc.before.len() - 1);
+            }
+        }
+        doc.positions.remove(self.as_key());
+        doc.reindex(parent, index);
+
+        true
+    }
+
+    /// Remove this node from the document tree, like the DOM's `ChildNode.remove()`.
+    ///
+    /// This is equivalent to [`Node::detach`], discarding whether the node was attached.
+    pub fn remove(self, doc: &mut Document) {
+        self.detach(doc);
+    }
+
+    /// Put another node in the place of this one, which is left detached.
+    ///
+    /// If the replacement is attached elsewhere, it is moved. The root element can only be
+    /// replaced by another element, which becomes the new root.
+    ///
+    /// ```
+    /// let mut doc = "<root><a/>b</root>".parse::<xmlem::Document>().unwrap();
+    /// let a = doc.root().child_nodes(&doc)[0];
+    /// let c = doc.create_comment("c");
+    /// a.replace_with(&mut doc, c).unwrap();
+    /// assert_eq!(doc.to_string(), "<root><!--c-->b</root>");
+    /// ```
+    pub fn replace_with(self, doc: &mut Document, node: impl Into<Node>) -> Result<(), Error> {
+        let node = node.into();
+        self.check(doc)?;
+        node.check(doc)?;
+        if node == self {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.parent(doc) {
+            parent.insert_before(doc, self, node)?;
+            self.detach(doc);
+            return Ok(());
+        }
+
+        if self == Node::Element(doc.root()) {
+            let element = node.as_element().ok_or(Error::Hierarchy(node))?;
+            node.detach(doc);
+            doc.root_key = element;
+            return Ok(());
+        }
+
+        if self.index_in_parent(doc).is_none() {
+            return Err(Error::Detached(self));
+        }
+        if matches!(node, Node::Element(_)) {
+            return Err(Error::Hierarchy(node));
+        }
+        node.detach(doc);
+        replace_in_container(doc, self, node);
+        Ok(())
+    }
+
+    /// Remove this node from the document and free it along with all of its descendants.
+    ///
+    /// Handles to the node or any of its descendants become stale. The root element cannot be
+    /// deleted; doing so does nothing.
+    ///
+    /// ```
+    /// let mut doc = "<root><a><b/></a></root>".parse::<xmlem::Document>().unwrap();
+    /// let a = doc.root().children(&doc)[0];
+    /// let b = a.children(&doc)[0];
+    /// a.as_node().delete(&mut doc);
+    /// assert!(!doc.contains(b.as_node()));
+    /// assert_eq!(doc.to_string(), "<root/>");
+    /// ```
+    pub fn delete(self, doc: &mut Document) {
+        if self == Node::Element(doc.root()) {
+            return;
+        }
+
+        self.detach(doc);
+        doc.free_subtree(self.as_key());
+    }
+
     pub fn to_ordinal(&self) -> u8 {
         match self {
             Node::Element(_) => 0,
@@ -124,3 +622,76 @@ impl Node {
         }
     }
 }
+
+/// The index of `node` in the top-level sequence, or `None` if it is detached. Detached nodes
+/// may still have a recorded position from where they used to be, so it must be verified.
+fn top_level_index(doc: &Document, node: Node) -> Option<usize> {
+    if node == Node::Element(doc.root()) {
+        return Some(doc.before.len());
+    }
+
+    let index = *doc.positions.get(node.as_key())?;
+    (top_level_node(doc, index) == Some(node)).then_some(index)
+}
+
+fn top_level_node(doc: &Document, index: usize) -> Option<Node> {
+    match index.cmp(&doc.before.len()) {
+        Ordering::Less => Some(doc.before[index]),
+        Ordering::Equal => Some(Node::Element(doc.root())),
+        Ordering::Greater => doc.after.get(index - doc.before.len() - 1).copied(),
+    }
+}
+
+/// Returns the list that holds `node` and its index in it: its parent's children, or the
+/// document's prolog or epilogue.
+fn container_mut(doc: &mut Document, node: Node) -> Option<(&mut Vec<Node>, usize)> {
+    let index = node.index_in_parent(doc)?;
+
+    if let Some(parent) = node.parent(doc) {
+        return Some((parent.children_mut(doc), index));
+    }
+
+    if index < doc.before.len() {
+        Some((&mut doc.before, index))
+    } else if index > doc.before.len() {
+        let offset = doc.before.len() + 1;
+        Some((&mut doc.after, index - offset))
+    } else {
+        None
+    }
+}
+
+/// Puts `new` in the place of `old`, which must not be referenced by its handle afterwards.
+pub(crate) fn replace_in_container(doc: &mut Document, old: Node, new: Node) {
+    let parent = old.parent(doc);
+    let index = old.index_in_parent(doc);
+    if let Some((nodes, i)) = container_mut(doc, old) {
+        nodes[i] = new;
+    }
+
+    if let Some(parent) = parent {
+        doc.parents.remove(old.as_key());
+        doc.parents.insert(new.as_key(), parent);
+    }
+    if let Some(index) = index {
+        doc.positions.remove(old.as_key());
+        doc.positions.insert(new.as_key(), index);
+    }
+}
+
+/// Inserts the detached node `new` directly after `reference`, if `reference` is attached.
+pub(crate) fn insert_sibling_after(doc: &mut Document, reference: Node, new: Node) {
+    let parent = reference.parent(doc);
+    let index = match container_mut(doc, reference) {
+        Some((nodes, i)) => {
+            nodes.insert(i + 1, new);
+            i + 1
+        }
+        None => return,
+    };
+
+    if let Some(parent) = parent {
+        doc.parents.insert(new.as_key(), parent);
+    }
+    doc.reindex(parent, index);
+}
@@ -1,18 +1,37 @@
+mod builder;
 pub mod display;
 mod document;
 mod element;
+mod error;
+pub mod iter;
 pub mod key;
+mod macros;
+mod path;
+mod query;
 mod select;
 mod value;
+mod view;
+mod xpath;
 
+pub use builder::ElementBuilder;
 pub use document::{Declaration, Document, ReadError};
 pub use element::{Element, NewElement};
+pub use error::{AttributeError, Error};
 pub use key::Node;
-pub use select::Selector;
+pub use macros::XmlChild;
+pub use query::Query;
+pub use select::{SelectedNode, Selector, SelectorParser};
+pub use view::{ElementMut, ElementRef};
+pub use xpath::{XPath, XPathError, XPathNode, XPathValue};
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::macros::{str_eq, MacroBuilder};
+}
 
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
+    use std::{collections::HashMap, str::FromStr};
 
     use qname::qname;
 
@@ -90,7 +109,7 @@ mod tests {
             },
         );
 
-        foo.parent(&mut doc)
+        foo.parent(&doc)
             .unwrap()
             .remove_child(&mut doc, Node::Element(foo));
 
@@ -206,7 +225,7 @@ mod tests {
         let include_selector = Selector::new("include").expect("this selector is fine");
         let rows_include = doc
             .root()
-            .query_selector(&mut doc, &include_selector)
+            .query_selector(&doc, &include_selector)
             .expect("there should be an include");
 
         let row_append = rows_include.append_new_element_after(
@@ -250,16 +269,1372 @@ mod tests {
         println!("{:#}", doc);
     }
 
+    #[test]
+    fn insert_positions() {
+        let mut doc = Document::from_str("<root><b/><d/></root>").unwrap();
+        let root = doc.root();
+        let b = root.children(&doc)[0];
+        let d = root.children(&doc)[1];
+
+        root.prepend_comment(&mut doc, "first");
+        root.insert_new_element_before(&mut doc, b, ("a", [("x", "1")]))
+            .unwrap();
+        root.insert_text_after(&mut doc, b, "c").unwrap();
+        root.insert_cdata_at(&mut doc, 5, "e").unwrap();
+        assert_eq!(
+            doc.to_string(),
+            r#"<root><!--first--><a x="1"/><b/>c<d/><![CDATA[e]]></root>"#
+        );
+
+        // Moving an existing node detaches it from its old position.
+        root.insert_before(&mut doc, b, d).unwrap();
+        assert_eq!(
+            doc.to_string(),
+            r#"<root><!--first--><a x="1"/><d/><b/>c<![CDATA[e]]></root>"#
+        );
+        assert_eq!(d.parent(&doc), Some(root));
+    }
+
+    #[test]
+    fn insert_errors() {
+        let mut doc = Document::from_str("<root><a><b/></a></root>").unwrap();
+        let root = doc.root();
+        let a = root.children(&doc)[0];
+        let b = a.children(&doc)[0];
+
+        assert_eq!(
+            root.insert_text_before(&mut doc, b, "x"),
+            Err(Error::NotAChild(b.as_node()))
+        );
+        assert_eq!(
+            root.insert_at(&mut doc, 2, b),
+            Err(Error::OutOfBounds { index: 2, len: 1 })
+        );
+        assert_eq!(b.append(&mut doc, a), Err(Error::Hierarchy(a.as_node())));
+        assert_eq!(
+            a.append(&mut doc, root),
+            Err(Error::Hierarchy(root.as_node()))
+        );
+        assert_eq!(doc.to_string(), "<root><a><b/></a></root>");
+
+        let len = doc.nodes.len();
+        assert_eq!(
+            root.try_append_new_element_after(&mut doc, ("c", [("n", "1")])),
+            Err(Error::Hierarchy(root.as_node()))
+        );
+        assert!(doc
+            .root_mut()
+            .try_append_new_element_after(("c", [("n", "1")]))
+            .is_err());
+        b.as_node().detach(&mut doc);
+        assert_eq!(
+            b.try_append_new_element_after(&mut doc, ("c", [("n", "1")])),
+            Err(Error::Detached(b.as_node()))
+        );
+        assert_eq!(doc.nodes.len(), len);
+        a.append(&mut doc, b).unwrap();
+        ElementMut::new(&mut doc, b)
+            .try_append_new_element_after(("c", [("n", "1")]))
+            .unwrap()
+            .append_text("d");
+        assert_eq!(doc.to_string(), r#"<root><a><b/><c n="1">d</c></a></root>"#);
+
+        let detached = doc.create_text("loose");
+        root.prepend(&mut doc, detached).unwrap();
+        assert_eq!(
+            doc.to_string(),
+            r#"<root>loose<a><b/><c n="1">d</c></a></root>"#
+        );
+    }
+
+    #[test]
+    fn node_navigation() {
+        let mut doc =
+            Document::from_str("<!-- pre --><root>a<!-- b --><![CDATA[c]]></root><!-- post -->")
+                .unwrap();
+        let root = doc.root();
+        let [a, b, c] = <[Node; 3]>::try_from(root.child_nodes(&doc)).unwrap();
+
+        assert_eq!(b.parent(&doc), Some(root));
+        assert_eq!(b.index_in_parent(&doc), Some(1));
+        assert_eq!(a.prev_sibling(&doc), None);
+        assert_eq!(b.prev_sibling(&doc), Some(a));
+        assert_eq!(b.next_sibling(&doc), Some(c));
+        assert_eq!(c.next_sibling(&doc), None);
+
+        let pre = doc.before[0];
+        let post = doc.after[0];
+        assert_eq!(pre.parent(&doc), None);
+        assert_eq!(pre.next_sibling(&doc), Some(root.as_node()));
+        assert_eq!(root.as_node().next_sibling(&doc), Some(post));
+        assert_eq!(post.prev_sibling(&doc), Some(root.as_node()));
+        assert_eq!(post.index_in_parent(&doc), Some(2));
+
+        assert!(b.detach(&mut doc));
+        assert!(!b.detach(&mut doc));
+        assert_eq!(b.parent(&doc), None);
+        assert_eq!(b.index_in_parent(&doc), None);
+        assert_eq!(c.prev_sibling(&doc), Some(a));
+
+        pre.remove(&mut doc);
+        assert!(!root.as_node().detach(&mut doc));
+        root.append(&mut doc, post).unwrap();
+        assert_eq!(doc.to_string(), "<root>a<![CDATA[c]]><!-- post --></root>");
+    }
+
+    #[test]
+    fn lazy_iterators() {
+        use crate::iter::NodeEdge;
+
+        let doc = Document::from_str("<a>t<b><c/>u</b><!--v--><d/></a>").unwrap();
+        let a = doc.root();
+        let [b, d] = <[Element; 2]>::try_from(a.children(&doc)).unwrap();
+        let c = b.children(&doc)[0];
+
+        assert_eq!(a.descendants(&doc).collect::<Vec<_>>(), [b, c, d]);
+        assert_eq!(a.descendant_nodes(&doc).count(), 6);
+        assert_eq!(b.descendants(&doc).collect::<Vec<_>>(), [c]);
+        assert_eq!(c.descendant_nodes(&doc).next(), None);
+        assert_eq!(c.ancestors(&doc).collect::<Vec<_>>(), [b, a]);
+        assert_eq!(a.ancestors(&doc).next(), None);
+
+        assert_eq!(b.following_siblings(&doc).collect::<Vec<_>>(), [d]);
+        assert_eq!(b.as_node().following_siblings(&doc).count(), 2);
+        assert_eq!(d.preceding_siblings(&doc).collect::<Vec<_>>(), [b]);
+        assert_eq!(d.prev_sibling_element(&doc), Some(b));
+        assert_eq!(a.child_elements(&doc).collect::<Vec<_>>(), [b, d]);
+        assert_eq!(a.child_count(&doc), 4);
+        assert_eq!(
+            a.first_child(&doc)
+                .and_then(Node::as_text)
+                .map(|t| t.as_str(&doc)),
+            Some("t")
+        );
+        assert_eq!(a.last_child(&doc), Some(d.as_node()));
+        assert_eq!(c.first_child(&doc), None);
+
+        let edges = b.traverse(&doc).collect::<Vec<_>>();
+        let u = b.child_nodes(&doc)[1];
+        assert_eq!(
+            edges,
+            [
+                NodeEdge::Enter(b.as_node()),
+                NodeEdge::Enter(c.as_node()),
+                NodeEdge::Leave(c.as_node()),
+                NodeEdge::Enter(u),
+                NodeEdge::Leave(u),
+                NodeEdge::Leave(b.as_node()),
+            ]
+        );
+        assert_eq!(a.traverse(&doc).count(), 14);
+    }
+
+    /// Checks every recorded sibling position against a search of the containing list.
+    fn assert_positions(doc: &Document) {
+        let top_level = doc
+            .before
+            .iter()
+            .copied()
+            .chain([doc.root().as_node()])
+            .chain(doc.after.iter().copied())
+            .collect::<Vec<_>>();
+        for (i, node) in top_level.iter().enumerate() {
+            assert_eq!(node.index_in_parent(doc), Some(i), "{node:?}");
+        }
+        for edge in doc.root().traverse(doc) {
+            if let iter::NodeEdge::Enter(Node::Element(e)) = edge {
+                for (i, node) in e.child_nodes(doc).iter().enumerate() {
+                    assert_eq!(doc.positions.get(node.as_key()), Some(&i), "{node:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sibling_positions() {
+        let mut doc = Document::from_str("<!--a--><root>b<c/><d/>e</root><!--f-->").unwrap();
+        assert_positions(&doc);
+        let root = doc.root();
+        let [b, c, d, e] = <[Node; 4]>::try_from(root.child_nodes(&doc)).unwrap();
+
+        root.prepend_text(&mut doc, "x");
+        root.insert_new_element_after(&mut doc, c, ("y", [("n", "1")]))
+            .unwrap();
+        assert_positions(&doc);
+        assert_eq!(
+            d.prev_sibling(&doc)
+                .unwrap()
+                .as_element()
+                .unwrap()
+                .name(&doc),
+            "y"
+        );
+
+        c.detach(&mut doc);
+        root.append(&mut doc, c).unwrap();
+        b.as_text().unwrap().split_at(&mut doc, 0);
+        e.as_text().unwrap().into_cdata(&mut doc);
+        assert_positions(&doc);
+
+        let wrapper = d
+            .as_element()
+            .unwrap()
+            .wrap(&mut doc, ("w", [("n", "2")]))
+            .unwrap();
+        root.wrap_children_range(&mut doc, 1..3, ("v", [("n", "3")]))
+            .unwrap();
+        assert_positions(&doc);
+        wrapper.unwrap(&mut doc).unwrap();
+        assert_positions(&doc);
+
+        let t = doc.create_text("t");
+        doc.before[0].replace_with(&mut doc, t).unwrap();
+        doc.after[0].detach(&mut doc);
+        assert_positions(&doc);
+
+        root.append_text(&mut doc, "");
+        root.append_text(&mut doc, "z");
+        doc.normalize();
+        doc.sort(true);
+        assert_positions(&doc);
+        doc.set_doctype(Some("html"));
+        assert_positions(&doc);
+
+        root.set_text(&mut doc, "done");
+        assert_positions(&doc);
+        assert_eq!(doc.to_string(), "<!DOCTYPE html>t<root>done</root>");
+    }
+
+    #[test]
+    fn xml_macro() {
+        let mut doc = Document::from_str(
+            r#"<merge xmlns:latin="http://schemas.android.com/apk/res-auto"><include/></merge>"#,
+        )
+        .unwrap();
+        let root = doc.root();
+        let width = 8.18;
+        let added = xml!(&mut doc, root =>
+            <Row>
+                <include "latin:keyboardLayout"="@xml/potato" "latin:keyWidth"={format!("{width}%p")}/>
+            </Row>
+            <Row/>
+        );
+        assert_eq!(added.len(), 2);
+        assert_eq!(added[1].as_element().unwrap().name(&doc), "Row");
+        assert_eq!(
+            doc.to_string(),
+            r#"<merge xmlns:latin="http://schemas.android.com/apk/res-auto"><include/><Row><include latin:keyboardLayout="@xml/potato" latin:keyWidth="8.18%p"/></Row><Row/></merge>"#
+        );
+
+        let items = (0..3).map(|i| xml!(<item>{i}</item>));
+        let doc = xml!(<list kind="numbers">{..items}<empty></empty></list>);
+        assert_eq!(
+            doc.to_string(),
+            r#"<list kind="numbers"><item>0</item><item>1</item><item>2</item><empty/></list>"#
+        );
+
+        let names = ["group", "item"];
+        let doc =
+            xml!(<{names[0]}><{names[1]} "a-b"="1">"x"</{names[1]}><"c-d"></"c-d"></{names[0]}>);
+        assert_eq!(
+            doc.to_string(),
+            r#"<group><item a-b="1">x</item><c-d/></group>"#
+        );
+    }
+
+    #[test]
+    fn element_builder() {
+        let mut row = ElementBuilder::new("Row");
+        for (i, code) in ["a", "b"].iter().enumerate() {
+            let mut key = ElementBuilder::new("key").attr("code", code);
+            if i == 0 {
+                key = key.attr("width", 1.5).cdata("<");
+            }
+            row = row.child(key);
+        }
+
+        let doc = row.clone().into_document();
+        assert_eq!(
+            doc.to_string(),
+            r#"<Row><key code="a" width="1.5"><![CDATA[<]]></key><key code="b"/></Row>"#
+        );
+
+        let mut doc = Document::from_str("<root><a/></root>").unwrap();
+        let a = doc.root().children(&doc)[0];
+        let nodes = row.append_to(&mut doc, a);
+        assert_eq!(nodes.len(), 4);
+        assert_eq!(nodes[0].parent(&doc), Some(a));
+        assert_eq!(nodes[3].parent(&doc), nodes[0].as_element());
+
+        let invalid = ElementBuilder::new("ok").child(ElementBuilder::new("x").attr("a b", 1));
+        assert_eq!(
+            invalid.try_append_to(&mut doc, a),
+            Err(Error::InvalidName("a b".into()))
+        );
+        assert_eq!(a.child_count(&doc), 1);
+
+        let doc = xml!(<list>{..(1..3).map(|i| ElementBuilder::new("item").text(i))}</list>);
+        assert_eq!(doc.to_string(), "<list><item>1</item><item>2</item></list>");
+    }
+
+    #[test]
+    fn typed_attributes() {
+        let mut doc =
+            Document::from_str(r#"<root><key x="12" keyWidth="8.18%p" flag=" true "/></root>"#)
+                .unwrap();
+        let key = doc.root().children(&doc)[0];
+
+        assert_eq!(key.attribute_as::<u8>(&doc, "x"), Ok(Some(12)));
+        let err = key.attribute_as::<f32>(&doc, "keyWidth").unwrap_err();
+        assert_eq!(err.element, "key");
+        assert_eq!(err.attribute, "keyWidth");
+        assert_eq!(err.value, "8.18%p");
+        assert!(std::error::Error::source(&err).is_some());
+        assert_eq!(key.attribute_bool(&doc, "flag"), Ok(Some(true)));
+        assert_eq!(key.attribute_bool(&doc, "missing"), Ok(None));
+
+        key.set_attribute_value(&mut doc, "x", 1.5);
+        assert_eq!(key.attribute_as::<f64>(&doc, "x"), Ok(Some(1.5)));
+
+        assert_eq!(key.attribute_tokens(&doc, "class").next(), None);
+        key.add_attribute_token(&mut doc, "class", "wide");
+        key.add_attribute_token(&mut doc, "class", "dark");
+        assert!(key.has_attribute_token(&doc, "class", "dark"));
+        key.remove_attribute_token(&mut doc, "class", "wide");
+        assert_eq!(key.attribute(&doc, "class"), Some("dark"));
+        assert!(Selector::new(".dark").unwrap().matches(&doc, key));
+    }
+
+    #[test]
+    fn element_views() {
+        let mut doc = Document::from_str("<root><a>x</a><b/></root>").unwrap();
+        let root = doc.root_ref();
+        let a = root.children().next().unwrap();
+        assert_eq!(a.name(), "a");
+        assert_eq!(a.text_content(), "x");
+        assert_eq!(a.next_sibling_element().map(ElementRef::name), Some("b"));
+        assert_eq!(a.ancestors().collect::<Vec<_>>(), [root]);
+        assert_eq!(
+            root.query_selector(&Selector::new("b").unwrap())
+                .unwrap()
+                .prev_sibling_element(),
+            Some(a)
+        );
+        let any = Selector::new("*").unwrap();
+        assert_eq!(
+            root.query_selector_iter(&any).collect::<Vec<_>>(),
+            root.query_selector_all(&any)
+        );
+        assert!(a.matches(&Selector::new("root > a").unwrap()));
+        assert!(!a.matches(&Selector::new("b").unwrap()));
+        assert_eq!(a.closest(&Selector::new("root").unwrap()), Some(root));
+        assert_eq!(a.closest(&Selector::new("b").unwrap()), None);
+        assert_eq!(a.path().unwrap(), "/root/a");
+        assert_eq!(a.path_with_keys(&["id"]).unwrap(), "/root/a");
+        let a = Element::from(a);
+        let b = doc.root().children(&doc)[1];
+
+        a.with_mut(&mut doc)
+            .set_attribute("n", 1)
+            .add_attribute_token("class", "x")
+            .append(b)
+            .set_name("c");
+        let b = doc
+            .root_mut()
+            .append_new_element(("b", [("y", "2")]))
+            .element();
+        assert_eq!(b.with(&doc).parent(), Some(doc.root_ref()));
+        assert_eq!(
+            doc.to_string(),
+            r#"<root><c n="1" class="x">x<b/></c><b y="2"/></root>"#
+        );
+    }
+
+    #[test]
+    fn selector_conformance() {
+        let doc = Document::from_str(concat!(
+            r#"<root id="root" xml:lang="en"><list id="list">"#,
+            r#"<item id="i1" class="a first"/><item id="i2" class="b"><!--c--></item>"#,
+            r#"<x:item id="i3" xmlns:x="urn:x" type="Foo"/><item id="i4" class="a" xml:lang="de-Latn-DE">  </item>"#,
+            r#"<other id="o1">text</other><item id="i5" class="b"><![CDATA[x]]></item>"#,
+            r#"</list><group id="g" xml:lang="fr-CA"><item id="i6"/></group></root>"#,
+        ))
+        .unwrap();
+        let root = doc.root();
+
+        let cases: &[(&str, &[&str])] = &[
+            ("item", &["i1", "i2", "i3", "i4", "i5", "i6"]),
+            (r"x\:item", &["i3"]),
+            (":root", &["root"]),
+            (":empty", &["i1", "i2", "i3", "i4", "i6"]),
+            (":not(:empty)", &["root", "list", "o1", "i5", "g"]),
+            (":is(other, group)", &["o1", "g"]),
+            (":where(.a, .b)", &["i1", "i2", "i4", "i5"]),
+            (":not(item, list)", &["root", "o1", "g"]),
+            ("list:has(> other)", &["list"]),
+            (":has(+ other)", &["i4"]),
+            (":has(~ .b)", &["i1", "i2", "i3", "i4", "o1"]),
+            ("root:has(item.a)", &["root"]),
+            (":is(list, group):has(> item:only-child)", &["g"]),
+            (":lang(en)", &["root", "list", "i1", "i2", "i3", "o1", "i5"]),
+            (":lang(de-DE)", &["i4"]),
+            (r#":lang("*-CA")"#, &["g", "i6"]),
+            (":lang(fr, de)", &["i4", "g", "i6"]),
+            (":lang(en-US)", &[]),
+            ("[type=foo]", &[]),
+            ("[type=foo i]", &["i3"]),
+            ("[type=Foo s]", &["i3"]),
+            ("[class~=a]", &["i1", "i4"]),
+            ("[class|=a]", &["i4"]),
+            ("[xml|lang]", &["root", "i4", "g"]),
+            ("x|item", &["i3"]),
+            ("|item", &["i1", "i2", "i4", "i5", "i6"]),
+            (":nth-child(2)", &["i2", "g"]),
+            (":nth-child(odd of .b)", &["i2"]),
+            (":nth-child(2n+3 of item)", &["i3", "i5"]),
+            ("item:nth-last-child(1)", &["i5", "i6"]),
+            (":nth-of-type(2)", &["i2"]),
+            ("item:nth-last-of-type(1)", &["i3", "i5", "i6"]),
+            ("item:only-of-type", &["i3", "i6"]),
+            ("item:first-child", &["i1", "i6"]),
+            ("list > :last-child", &["i5"]),
+            ("item + item", &["i2", "i3", "i4"]),
+            ("group item, list > .first", &["i1", "i6"]),
+        ];
+
+        // Type selectors match the full name by default.
+        let exact: &[(&str, &[&str])] = &[
+            ("item", &["i1", "i2", "i4", "i5", "i6"]),
+            (r"x\:item", &["i3"]),
+            ("x|item", &[]),
+            ("|item", &[]),
+            (":nth-child(2n+3 of item)", &["i4"]),
+            ("item:only-of-type", &["i6"]),
+            ("item + item", &["i2"]),
+        ];
+
+        let local_names = SelectorParser::new().local_names(true);
+        for (parser, cases) in [(&local_names, cases), (&SelectorParser::new(), exact)] {
+            for (selector, expected) in cases {
+                let parsed = parser
+                    .parse(selector)
+                    .unwrap_or_else(|e| panic!("{selector}: {e:?}"));
+                let ids = std::iter::once(root)
+                    .chain(root.descendants(&doc))
+                    .filter(|e| parsed.matches(&doc, *e))
+                    .map(|e| e.attribute(&doc, "id").unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(&ids, expected, "{selector} {parser:?}");
+            }
+        }
+
+        let list = root.children(&doc)[0];
+        let ids = |scope: Element, selector: &str| {
+            scope
+                .query_selector_all(&doc, &local_names.parse(selector).unwrap())
+                .iter()
+                .map(|e| e.attribute(&doc, "id").unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(root, ":scope > *"), ["list", "g"]);
+        assert_eq!(ids(list, ":scope > .a"), ["i1", "i4"]);
+        assert_eq!(ids(list, ":scope item"), ["i1", "i2", "i3", "i4", "i5"]);
+        assert_eq!(ids(list, ":scope"), Vec::<&str>::new());
+        let item = Selector::new(":scope item").unwrap();
+        assert_eq!(list.query_selector_all(&doc, &item).len(), 4);
+
+        for invalid in [":hover", ":lang()", ":nth-child(x)", "::before", ":has()"] {
+            assert!(Selector::new(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn select_nodes() {
+        let doc = Document::from_str(
+            r#"<keyboard xmlns:x="urn:x"><row n="1" x:n="a">q<![CDATA[w]]><!--one--><key/></row><row n="2"><key code="e"/></row></keyboard>"#,
+        )
+        .unwrap();
+        let root = doc.root();
+        let texts = |selector: &str| {
+            Selector::new(selector)
+                .unwrap()
+                .select_nodes(&doc, root)
+                .iter()
+                .map(|n| n.text(&doc).into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(texts("row::attr(n)"), ["1", "2"]);
+        assert_eq!(texts("::attr(x|n)"), ["a"]);
+        assert_eq!(texts(r#"row::attr("x:n")"#), ["a"]);
+        assert_eq!(texts("row::text"), ["q", "w"]);
+        assert_eq!(texts("::comment"), ["one"]);
+        assert_eq!(texts("row:has(key[code])::attr(n)"), ["2"]);
+        assert_eq!(texts("key::attr(code)"), ["e"]);
+        assert_eq!(
+            texts("row::comment, row::text, row::attr(n), row::attr(n), row, row::text"),
+            ["qw", "1", "q", "w", "one", "", "2"]
+        );
+
+        let nodes = Selector::new("row::attr(n), row::comment")
+            .unwrap()
+            .select_nodes(&doc, root);
+        let row = root.children(&doc)[0];
+        assert_eq!(
+            nodes[0],
+            SelectedNode::Attribute {
+                element: row,
+                name: &qname!("n"),
+                value: "1"
+            }
+        );
+        assert_eq!(nodes[1].as_node(), Some(row.child_nodes(&doc)[2]));
+        assert_eq!(nodes[0].as_node(), None);
+
+        assert!(!Selector::new("row::text").unwrap().matches(&doc, row));
+        assert!(Selector::new("row::text, row").unwrap().matches(&doc, row));
+        for invalid in [
+            "::attr()",
+            "::attr(1)",
+            "::bogus",
+            "row::text::comment",
+            "::text row",
+        ] {
+            assert!(Selector::new(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn custom_pseudo_classes() {
+        let doc = Document::from_str(
+            r#"<resources><string name="a" translatable="false">x</string><string name="b">y z</string><plurals name="c"><item>1</item></plurals></resources>"#,
+        )
+        .unwrap();
+        let parser = SelectorParser::new()
+            .pseudo_class("Translatable", |doc, e| {
+                e.attribute(doc, "translatable") != Some("false")
+            })
+            .pseudo_class_with_arguments("args", |doc, e, args| {
+                args.join("|") == e.attribute(doc, "name").unwrap_or_default()
+            })
+            .pseudo_class_with_arguments("lang", |_, _, _| true);
+        let names = |selector: &str| {
+            let selector = parser.parse(selector).unwrap();
+            doc.root()
+                .query_selector_all(&doc, &selector)
+                .iter()
+                .map(|e| e.attribute(&doc, "name").unwrap_or("-"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names("string:translatable"), ["b"]);
+        assert_eq!(names(":TRANSLATABLE:not(item)"), ["b", "c"]);
+        assert_eq!(names(":not(:translatable)"), ["a"]);
+        assert_eq!(names(":has(> :translatable)"), ["c"]);
+        assert_eq!(names(":args(a)"), ["a"]);
+        assert_eq!(names(r#":args("b"), :args(c d, 2.5)"#), ["b"]);
+        assert_eq!(names(":lang(en)"), Vec::<&str>::new());
+
+        let raw = SelectorParser::new()
+            .pseudo_class_with_arguments("raw", |_, _, args| args == ["c d", "2.5", "x"])
+            .parse(r#"plurals:raw( c d , 2.5, "x")"#)
+            .unwrap();
+        assert!(raw.matches(&doc, doc.root().children(&doc)[2]));
+
+        for invalid in [":translatable(x)", ":args", ":unknown", ":unknown(x)"] {
+            assert!(parser.parse(invalid).is_err(), "{invalid}");
+        }
+        assert!(Selector::new(":translatable").is_err());
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Selector>();
+        assert_send_sync::<SelectorParser>();
+    }
+
+    #[test]
+    fn root_inclusive_queries() {
+        let doc = Document::from_str(
+            r#"<resources name="r"><plurals name="a"><item quantity="one">1</item><item>2</item></plurals><string name="b"/></resources>"#,
+        )
+        .unwrap();
+        let root = doc.root();
+        let selector = |s: &str| Selector::new(s).unwrap();
+        let names = |elements: Vec<Element>| {
+            elements
+                .iter()
+                .map(|e| e.attribute(&doc, "name").unwrap_or("-"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(doc.query_selector_all(&selector("[name]"))),
+            ["r", "a", "b"]
+        );
+        assert_eq!(
+            names(root.query_selector_all(&doc, &selector("[name]"))),
+            ["a", "b"]
+        );
+        assert_eq!(doc.query_selector(&selector(":scope")), Some(root));
+        assert_eq!(root.query_selector(&doc, &selector("resources")), None);
+        assert_eq!(
+            names(
+                doc.query_selector_iter(&selector(":nth-child(2)"))
+                    .collect()
+            ),
+            ["-", "b"]
+        );
+
+        let items = selector("item");
+        let mut iter = root.query_selector_iter(&doc, &items);
+        let item = iter.next().unwrap();
+        assert_eq!(item.text_content(&doc), "1");
+        assert_eq!(iter.next().unwrap().text_content(&doc), "2");
+        assert_eq!(iter.next(), None);
+
+        assert!(item.matches(&doc, &selector("[quantity]:scope")));
+        assert!(!item.matches(&doc, &selector("plurals")));
+        assert_eq!(
+            names(
+                item.closest(&doc, &selector("[name]"))
+                    .into_iter()
+                    .collect()
+            ),
+            ["a"]
+        );
+        assert_eq!(item.closest(&doc, &selector(":scope")), Some(item));
+        assert_eq!(
+            item.closest(&doc, &selector("resources > *"))
+                .map(|e| e.attribute(&doc, "name")),
+            Some(Some("a"))
+        );
+        assert_eq!(item.closest(&doc, &selector(":root")), Some(root));
+        assert_eq!(item.closest(&doc, &selector("string")), None);
+        assert_eq!(
+            item.closest(&doc, &selector(":has(> :scope)")),
+            root.query_selector(&doc, &selector("plurals"))
+        );
+    }
+
+    #[test]
+    fn batch_matching() {
+        let mut doc = Document::new("resources");
+        let root = doc.root();
+        for i in 0..30 {
+            let name = format!("s{i}");
+            let class = ["a", "b c", "C"][i % 3];
+            let parent = match i % 4 {
+                0 => root.append_new_element(&mut doc, ("x:plurals", [("name", name.as_str())])),
+                1 => root.append_new_element(&mut doc, ("String", [("id", name.as_str())])),
+                _ => root.append_new_element(&mut doc, ("array", [("class", class)])),
+            };
+            for j in 0..i % 5 {
+                let item = parent.append_new_element(&mut doc, ("item", [("x:q", j.to_string())]));
+                if j % 2 == 1 {
+                    item.append_new_element(&mut doc, ("Item", [("class", class)]));
+                }
+            }
+        }
+
+        let scope = root.children(&doc)[6];
+        for selector in [
+            "resources item",
+            "resources > array item",
+            "x|plurals item",
+            "plurals > item",
+            "x\\:plurals item:nth-child(2n)",
+            "#s9 item, #s13 > item",
+            "String > item, resources String item:last-child",
+            ".c Item",
+            ".C item > Item",
+            "[name] > item:nth-last-child(1)",
+            "[x|q] Item:nth-of-type(1)",
+            "array:nth-child(3n of [class]) item",
+            ":is(String, array) :is(item, Item)",
+            ":is(String) item, :where(array.c) > item",
+            ":is(resources array) item",
+            ":is(array item) > Item",
+            ":where(resources > array.c) item",
+            ":is(String + array) item",
+            ":is(String ~ array > item) Item",
+            ":is(String + array, x\\:plurals ~ String) item",
+            ":is(array, Item) :is(resources item) Item",
+            "resources :not(array) > item",
+            "array:not(.c) item",
+            ":not(String) Item",
+            ":is(array:not(:first-child)) item:not(:last-child)",
+            "array:has(Item) item:not(:first-child)",
+            ":scope > item",
+            "resources > :scope item",
+        ] {
+            let mut found = false;
+            for parser in [
+                SelectorParser::new(),
+                SelectorParser::new().local_names(true),
+            ] {
+                let selector = parser.parse(selector).unwrap();
+                let expected = |scope: Element, inclusive: bool| {
+                    std::iter::once(scope)
+                        .filter(|_| inclusive)
+                        .chain(scope.descendants(&doc))
+                        .filter(|e| selector.matches_in_scope(&doc, *e, Some(scope)))
+                        .collect::<Vec<_>>()
+                };
+                found |= !expected(root, true).is_empty() || !expected(scope, false).is_empty();
+                assert_eq!(doc.query_selector_all(&selector), expected(root, true));
+                assert_eq!(
+                    root.query_selector_all(&doc, &selector),
+                    expected(root, false)
+                );
+                assert_eq!(
+                    scope.query_selector_all(&doc, &selector),
+                    expected(scope, false)
+                );
+                for element in root.descendants(&doc) {
+                    let closest = std::iter::once(element)
+                        .chain(element.ancestors(&doc))
+                        .find(|e| selector.matches_in_scope(&doc, *e, Some(element)));
+                    assert_eq!(element.closest(&doc, &selector), closest);
+                }
+            }
+            assert!(found, "{selector}");
+        }
+    }
+
+    #[test]
+    fn query_builder() {
+        let doc = Document::from_str(
+            r#"<resources xmlns:tools="urn:tools"><string name='a"]\b' tools:ignore="x">1</string><string name="b" class="long  short" id="s">2</string><x:Row xmlns:x="urn:x"/><plurals name="c"><item quantity="one">3</item></plurals></resources>"#,
+        )
+        .unwrap();
+        let root = doc.root();
+        let texts = |query: Query| {
+            root.query_selector_all(&doc, &Selector::try_from(&query).unwrap())
+                .iter()
+                .map(|e| e.text_content(&doc))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            texts(Query::name("string").attr_eq("name", r#"a"]\b"#)),
+            ["1"]
+        );
+        assert_eq!(texts(Query::any().attr("tools:ignore")), ["1"]);
+        assert_eq!(texts(Query::name("string").class("short").id("s")), ["2"]);
+        assert_eq!(
+            texts(Query::name("string").class("long short")),
+            Vec::<String>::new()
+        );
+        assert_eq!(texts(Query::any().attr_starts_with("name", "a\"")), ["1"]);
+        assert_eq!(texts(Query::any().attr_ends_with("name", "\\b")), ["1"]);
+        assert_eq!(texts(Query::any().attr_contains("quantity", "n")), ["3"]);
+        assert_eq!(
+            texts(Query::name("resources").child(Query::any().not(Query::name("string")))),
+            ["", "3"]
+        );
+        assert_eq!(
+            texts(Query::name("resources").descendant(Query::name("item"))),
+            ["3"]
+        );
+        assert_eq!(texts(Query::any().has(Query::name("item")).unwrap()), ["3"]);
+        assert_eq!(
+            texts(Query::name("string").next_sibling(Query::any())),
+            ["2", ""]
+        );
+        assert_eq!(
+            texts(Query::name("string").subsequent_sibling(Query::any().attr("name"))),
+            ["2", "3"]
+        );
+        assert_eq!(texts(Query::name("x:Row")), [""]);
+        assert_eq!(texts(Query::name("Row")), Vec::<String>::new());
+        assert_eq!(texts(Query::name("")), Vec::<String>::new());
+        assert_eq!(texts(Query::any().attr_eq("", "")), Vec::<String>::new());
+
+        let query = Query::name("plurals")
+            .attr_eq("name", "c")
+            .child(Query::name("item"));
+        assert_eq!(query.to_string(), r#"plurals[name="c"] > item"#);
+        let parsed = Selector::new(&query.to_string()).unwrap();
+        let built = Selector::try_from(&query).unwrap();
+        for element in root.descendants(&doc) {
+            assert_eq!(built.matches(&doc, element), parsed.matches(&doc, element));
+        }
+
+        // `:has()` cannot be nested, however deep the inner one is.
+        let has_item = || Query::name("plurals").has(Query::name("item")).unwrap();
+        for nested in [
+            has_item(),
+            Query::any().not(has_item()),
+            Query::name("resources").child(has_item()),
+            Query::any().not(Query::name("string").next_sibling(has_item())),
+        ] {
+            let css = nested.to_string();
+            assert_eq!(Query::any().has(nested), Err(Error::NestedHas(css.clone())));
+            assert!(Selector::new(&format!("*:has({css})")).is_err(), "{css}");
+        }
+        let query = Query::any().has(Query::any().not(Query::name("string")));
+        assert_eq!(query.unwrap().to_string(), "*:has(*:not(string))");
+        let query = Query::name("resources")
+            .has(Query::name("item"))
+            .unwrap()
+            .child(has_item());
+        assert_eq!(texts(query), ["3"]);
+    }
+
+    #[test]
+    fn xpath() {
+        let doc = Document::from_str(
+            r#"<!--top--><merge xmlns="urn:default" xmlns:a="urn:a"><Row n="1"><key code="q"/><key code="w" a:x="1"/></Row><!--c--><Row n="2" xml:lang="en-GB"><key code="e">E<![CDATA[!]]></key></Row><a:Row id="r3"/></merge>"#,
+        )
+        .unwrap();
+        let eval = |expr: &str| {
+            XPath::with_namespaces(expr, [("d", "urn:default"), ("b", "urn:a")])
+                .unwrap()
+                .evaluate(&doc, XPathNode::Document)
+                .unwrap()
+        };
+        let string = |expr: &str| eval(expr).to_string_value(&doc);
+        let strings = |expr: &str| -> Vec<String> {
+            match eval(expr) {
+                XPathValue::Nodes(nodes) => nodes.iter().map(|n| n.string_value(&doc)).collect(),
+                value => panic!("not a node-set: {value:?}"),
+            }
+        };
+
+        // Unprefixed names only match elements in no namespace.
+        assert_eq!(eval("/merge"), XPathValue::Nodes(vec![]));
+        assert_eq!(string("count(//d:key)"), "3");
+        assert_eq!(string("count(//b:*)"), "1");
+        assert_eq!(strings("//d:key/@code"), ["q", "w", "e"]);
+        assert_eq!(strings("//@b:x"), ["1"]);
+        assert_eq!(strings("/d:merge/d:Row[2]/d:key/text()"), ["E", "!"]);
+        assert_eq!(strings("//comment()"), ["top", "c"]);
+        assert_eq!(strings("//d:key[1]/@code"), ["q", "e"]);
+        assert_eq!(strings("(//d:key)[last()]/@code"), ["e"]);
+        assert_eq!(strings("//d:key[@b:x]/preceding-sibling::*/@code"), ["q"]);
+        assert_eq!(strings("//d:key[@code='e']/ancestor::*[1]/@n"), ["2"]);
+        assert_eq!(strings("//d:key[@code='q']/following::*/@code"), ["w", "e"]);
+        assert_eq!(
+            strings("//d:key[@code='e']/preceding::d:key/@code"),
+            ["q", "w"]
+        );
+        assert_eq!(
+            strings("//d:Row[d:key/@code = 'w']/following-sibling::node()"),
+            ["c", "E!", ""]
+        );
+        assert_eq!(
+            strings("//d:key[@code='w']/@b:x/../@code | //d:Row/@n"),
+            ["1", "w", "2"]
+        );
+        assert_eq!(strings("id('x r3')/@id"), ["r3"]);
+        assert_eq!(string("name(//*[@id])"), "a:Row");
+        assert_eq!(string("local-name(//*[@id])"), "Row");
+        assert_eq!(string("namespace-uri(/*)"), "urn:default");
+        assert_eq!(string("count(//d:key[lang('en')])"), "1");
+
+        assert_eq!(eval("//d:Row/@n = 2"), XPathValue::Boolean(true));
+        assert_eq!(eval("//d:Row/@n != 2"), XPathValue::Boolean(true));
+        assert_eq!(eval("//d:Row/@n > 2"), XPathValue::Boolean(false));
+        assert_eq!(eval("//d:nothing = false()"), XPathValue::Boolean(true));
+        assert_eq!(eval("'1.0' = 1"), XPathValue::Boolean(true));
+        assert_eq!(eval("sum(//@n) div 4 * 2 mod 5"), XPathValue::Number(1.5));
+        assert_eq!(string("-(3 - 5) div 0"), "Infinity");
+        assert_eq!(string("number('x') + 1"), "NaN");
+        assert_eq!(string("round(-2.5)"), "-2");
+        assert_eq!(string("round(2.5) + floor(-1.5) + ceiling(1.2)"), "3");
+        assert_eq!(string("1 div 8"), "0.125");
+        assert_eq!(string("substring('12345', 1.5, 2.6)"), "234");
+        assert_eq!(string("substring('12345', 0, 3)"), "12");
+        assert_eq!(string("translate('bar', 'abc', 'AB')"), "BAr");
+        assert_eq!(string("normalize-space('  a \n b ')"), "a b");
+        assert_eq!(
+            string("concat(substring-before('a=b', '='), substring-after('a=b', '='))"),
+            "ab"
+        );
+        assert_eq!(string("string-length('héllo')"), "5");
+        assert_eq!(
+            string("boolean(//d:key) and not(starts-with('abc', 'b'))"),
+            "true"
+        );
+
+        let row = doc.root().children(&doc)[1];
+        let relative =
+            XPath::with_namespaces("d:key/@code | @* | ../@*", [("d", "urn:default")]).unwrap();
+        assert_eq!(relative.select(&doc, row).unwrap().len(), 3);
+        assert_eq!(relative.to_string(), "d:key/@code | @* | ../@*");
+
+        let variables = HashMap::from([
+            ("code".to_string(), XPathValue::String("w".into())),
+            (
+                "rows".to_string(),
+                XPath::new("//*[@n]")
+                    .unwrap()
+                    .evaluate(&doc, XPathNode::Document)
+                    .unwrap(),
+            ),
+        ]);
+        let with_variables = XPath::new("count($rows/*[@code = $code])").unwrap();
+        assert_eq!(
+            with_variables.evaluate_with(&doc, XPathNode::Document, &variables),
+            Ok(XPathValue::Number(1.0))
+        );
+        assert_eq!(
+            with_variables.evaluate(&doc, XPathNode::Document),
+            Err(XPathError::UnknownVariable("rows".into()))
+        );
+    }
+
+    #[test]
+    fn xpath_errors() {
+        let doc = Document::from_str("<root/>").unwrap();
+        let syntax = |expr: &str| match XPath::new(expr) {
+            Err(XPathError::Syntax { position, .. }) => position,
+            other => panic!("{expr}: {other:?}"),
+        };
+        assert_eq!(syntax("//a["), 4);
+        assert_eq!(syntax("'abc"), 0);
+        assert_eq!(syntax("a b"), 2);
+        assert_eq!(syntax("foo(1)"), 0);
+        assert_eq!(syntax("count()"), 6);
+        assert_eq!(syntax("bogus::a"), 0);
+        assert_eq!(syntax("a/"), 2);
+        assert_eq!(
+            XPath::new("x:a").unwrap_err(),
+            XPathError::UnboundPrefix("x".into())
+        );
+        assert_eq!(
+            XPath::new("count(1)").unwrap().evaluate(&doc, doc.root()),
+            Err(XPathError::NotANodeSet)
+        );
+        assert!(XPath::new("div div div").is_ok());
+        assert_eq!(
+            XPath::new("/").unwrap().select(&doc, doc.root()),
+            Ok(vec![XPathNode::Document])
+        );
+    }
+
+    #[test]
+    fn element_paths() {
+        let doc = Document::from_str(
+            r#"<x:merge xmlns:x="urn:x"><include id="it's"/><include name="a/b"/><include/><x:Row><include/></x:Row></x:merge>"#,
+        )
+        .unwrap();
+        let root = doc.root();
+        for element in std::iter::once(root).chain(root.descendants(&doc)) {
+            assert_eq!(
+                doc.resolve_path(&element.path(&doc).unwrap()),
+                Some(element)
+            );
+            let keyed = element.path_with_keys(&doc, &["id", "name"]).unwrap();
+            assert_eq!(doc.resolve_path(&keyed), Some(element), "{keyed}");
+        }
+
+        let [first, second, third, row] = <[Element; 4]>::try_from(root.children(&doc)).unwrap();
+        assert_eq!(
+            first.path_with_keys(&doc, &["id"]).unwrap(),
+            r#"/x:merge/include[@id="it's"]"#
+        );
+        assert_eq!(
+            second.path_with_keys(&doc, &["id", "name"]).unwrap(),
+            "/x:merge/include[@name='a/b']"
+        );
+        assert_eq!(
+            third.path_with_keys(&doc, &["id"]).unwrap(),
+            "/x:merge/include[3]"
+        );
+        assert_eq!(
+            row.children(&doc)[0].path(&doc).unwrap(),
+            "/x:merge/x:Row/include"
+        );
+        assert_eq!(
+            doc.resolve_path("/x:merge/include[@name='a/b'][1]"),
+            Some(second)
+        );
+
+        for invalid in [
+            "",
+            "x:merge",
+            "/merge",
+            "/x:merge/include[4]",
+            "/x:merge/include[0]",
+        ] {
+            assert_eq!(doc.resolve_path(invalid), None, "{invalid}");
+        }
+        assert_eq!(doc.resolve_path("/x:merge/include[@id='x"), None);
+
+        // A detached subtree whose top has the root's name must not map onto the document.
+        let mut doc = doc;
+        let root = doc.root();
+        let detached = doc.create_element(("x:merge", [("n", "1")]));
+        let include = detached.append_new_element(&mut doc, ("include", [("id", "it's")]));
+        assert_eq!(detached.path(&doc), None);
+        assert_eq!(include.path(&doc), None);
+        assert_eq!(include.path_with_keys(&doc, &["id"]), None);
+        let first = root.children(&doc)[0];
+        first.as_node().detach(&mut doc);
+        assert_eq!(first.path(&doc), None);
+        assert_eq!(doc.resolve_path("/x:merge/include[3]"), None);
+    }
+
+    #[test]
+    fn edit_character_data() {
+        let mut doc = Document::from_str("<root>a<!--b--><![CDATA[c]]></root>").unwrap();
+        let root = doc.root();
+        let [a, b, c] = <[Node; 3]>::try_from(root.child_nodes(&doc)).unwrap();
+
+        a.as_text().unwrap().set(&mut doc, "x");
+        b.as_comment().unwrap().set(&mut doc, "y");
+        c.as_cdata().unwrap().set(&mut doc, "z]]>");
+        assert_eq!(
+            doc.to_string(),
+            "<root>x<!--y--><![CDATA[z]]]]><![CDATA[>]]></root>"
+        );
+
+        let text = c.as_cdata().unwrap().into_text(&mut doc);
+        assert_eq!(root.child_nodes(&doc)[2], Node::Text(text));
+        assert_eq!(text.as_str(&doc), "z]]>");
+        let cdata = a.as_text().unwrap().into_cdata(&mut doc);
+        assert_eq!(Node::CDataSection(cdata).parent(&doc), Some(root));
+        assert_eq!(doc.to_string(), "<root><![CDATA[x]]><!--y-->z]]&gt;</root>");
+
+        let comment = b.as_comment().unwrap();
+        for invalid in ["a--b", "--", "a-", "-"] {
+            assert_eq!(
+                comment.try_set(&mut doc, invalid),
+                Err(Error::InvalidComment(invalid.to_string()))
+            );
+        }
+        comment.try_set(&mut doc, "-a-b").unwrap();
+        assert_eq!(comment.as_str(&doc), "-a-b");
+
+        root.remove_child(&mut doc, b);
+        root.remove_child(&mut doc, Node::CDataSection(cdata));
+        root.remove_child(&mut doc, Node::Text(text));
+        doc.compact([]);
+        assert_eq!(comment.try_set(&mut doc, "z"), Err(Error::NodeNotFound(b)));
+        assert!(cdata.try_set(&mut doc, "z").is_err());
+        assert!(text.try_set(&mut doc, "z").is_err());
+        assert_eq!(doc.to_string(), "<root/>");
+    }
+
+    #[test]
+    fn split_and_normalize() {
+        let mut doc = Document::from_str("<root>Hello world<a/></root>").unwrap();
+        let root = doc.root();
+        let hello = root.child_nodes(&doc)[0].as_text().unwrap();
+
+        let world = hello.split_at(&mut doc, 5);
+        let empty = world.split_at(&mut doc, 6);
+        assert_eq!(root.child_nodes(&doc).len(), 4);
+        assert_eq!(Node::Text(world).parent(&doc), Some(root));
+        assert_eq!(empty.as_str(&doc), "");
+
+        doc.normalize();
+        assert_eq!(
+            root.child_nodes(&doc),
+            &[Node::Text(hello), root.children(&doc)[0].as_node()]
+        );
+        assert_eq!(hello.as_str(&doc), "Hello world");
+        assert!(doc.contains(hello.into()));
+        assert!(!doc.contains(world.into()));
+        assert!(!doc.contains(empty.into()));
+        assert_eq!(doc.nodes.len(), 3);
+
+        let first = root.append_text(&mut doc, "!");
+        let merged = root.append_text(&mut doc, "?");
+        doc.normalize();
+        assert!(doc.contains(first.into()));
+        assert!(!doc.contains(merged.into()));
+        assert_eq!(doc.to_string(), "<root>Hello world<a/>!?</root>");
+    }
+
     #[test]
     fn set_text() {
-        let input = "<root><a/></root>";
+        let input = "<root><a><b/></a></root>";
         let mut doc = Document::from_str(input).unwrap();
 
         doc.root().set_text(&mut doc, "potato");
+        assert_eq!(doc.nodes.len(), 2);
+        let text = doc.root().child_nodes(&doc)[0];
+        assert_eq!(text.parent(&doc), Some(doc.root()));
+        assert_eq!(doc.root().text_content(&doc), "potato");
 
         println!("{:#}", doc);
     }
 
+    #[test]
+    fn compact_frees_unreachable() {
+        let mut doc = Document::from_str("<!--pre--><root><a><b/>text</a><c/></root>").unwrap();
+        let root = doc.root();
+        let [a, c] = <[Element; 2]>::try_from(root.children(&doc)).unwrap();
+        let b = a.children(&doc)[0];
+
+        doc.set_doctype(Some("root"));
+        doc.set_doctype(None);
+        assert_eq!(doc.nodes.len(), 6);
+
+        c.as_node().delete(&mut doc);
+        assert!(!doc.contains(c.as_node()));
+        assert_eq!(doc.nodes.len(), 5);
+
+        root.as_node().delete(&mut doc);
+        assert!(doc.contains(root.as_node()));
+
+        a.as_node().detach(&mut doc);
+        doc.compact([b.as_node()]);
+        assert!(!doc.contains(a.as_node()));
+        assert!(doc.contains(b.as_node()));
+        assert_eq!(b.parent(&doc), None);
+        assert_eq!(doc.nodes.len(), 3);
+        assert_eq!(doc.parents.len(), 0);
+        assert_eq!(doc.to_string(), "<!--pre--><root/>");
+
+        root.append(&mut doc, b).unwrap();
+        assert_eq!(doc.to_string(), "<!--pre--><root><b/></root>");
+    }
+
+    #[test]
+    fn deep_clone_and_import() {
+        let mut doc = Document::from_str(
+            r#"<root><a x="1"><b>text<![CDATA[cdata]]><!--comment--></b></a></root>"#,
+        )
+        .unwrap();
+        let root = doc.root();
+        let a = root.children(&doc)[0];
+
+        let copy = a.deep_clone(&mut doc);
+        assert_ne!(copy, a);
+        assert_eq!(copy.parent(&doc), None);
+        let b = copy.children(&doc)[0];
+        assert_eq!(b.parent(&doc), Some(copy));
+        assert_eq!(b.child_nodes(&doc)[1].parent(&doc), Some(b));
+
+        copy.set_attribute(&mut doc, "x", "2");
+        b.set_text(&mut doc, "changed");
+        assert_eq!(a.attribute(&doc, "x"), Some("1"));
+        assert_eq!(a.text_content(&doc), "textcdata");
+        assert_eq!(copy.text_content(&doc), "changed");
+
+        let mut other = Document::new("other");
+        let imported = other.import_node(&doc, a.as_node());
+        other.root().append(&mut other, imported).unwrap();
+        let text = other.import_node(&doc, b.child_nodes(&doc)[0]);
+        other.root().append(&mut other, text).unwrap();
+        assert_eq!(
+            other.to_string(),
+            r#"<other><a x="1"><b>text<![CDATA[cdata]]><!--comment--></b></a>changed</other>"#
+        );
+    }
+
+    #[test]
+    fn subtree_to_document() {
+        let doc = Document::from_str(
+            r#"<?xml version="1.0"?><merge xmlns="urn:default" xmlns:a="urn:a" xmlns:b="urn:b">
+                <section xmlns:a="urn:a2"><item a:x="1" b:y="2" xmlns:b="urn:b3">text</item></section>
+            </merge>"#,
+        )
+        .unwrap();
+        let section = doc.root().children(&doc)[0];
+        let item = section.children(&doc)[0];
+
+        let item_doc = item.to_document(&doc);
+        assert_eq!(item_doc.root().name(&item_doc), "item");
+        assert_eq!(item_doc.nodes.len(), 2);
+        assert_eq!(
+            item_doc.to_string(),
+            r#"<?xml version="1.0"?><item xmlns="urn:default" xmlns:a="urn:a2" a:x="1" b:y="2" xmlns:b="urn:b3">text</item>"#
+        );
+
+        let root_doc = doc.root().to_document(&doc);
+        assert_eq!(
+            root_doc.root().attributes(&root_doc),
+            doc.root().attributes(&doc)
+        );
+    }
+
+    #[test]
+    fn replace_wrap_unwrap() {
+        let mut doc = Document::from_str("<!--pre--><root><a/><b/><c/></root>").unwrap();
+        let root = doc.root();
+        let [a, b, c] = <[Element; 3]>::try_from(root.children(&doc)).unwrap();
+
+        let row = b.wrap(&mut doc, ("Row", [("n", "1")])).unwrap();
+        assert_eq!(b.parent(&doc), Some(row));
+        assert_eq!(row.parent(&doc), Some(root));
+        assert_eq!(
+            root.wrap_children_range(&mut doc, 2..4, NewElement::from(("x", [("y", "z")]))),
+            Err(Error::OutOfBounds { index: 4, len: 3 })
+        );
+
+        a.as_node().replace_with(&mut doc, c).unwrap();
+        assert_eq!(a.parent(&doc), None);
+        assert_eq!(c.as_node().index_in_parent(&doc), Some(0));
+        assert_eq!(
+            doc.to_string(),
+            r#"<!--pre--><root><c/><Row n="1"><b/></Row></root>"#
+        );
+
+        assert_eq!(a.unwrap(&mut doc), Err(Error::Detached(a.as_node())));
+        assert_eq!(root.unwrap(&mut doc), Err(Error::Hierarchy(root.as_node())));
+        row.unwrap(&mut doc).unwrap();
+        assert_eq!(b.parent(&doc), Some(root));
+        assert_eq!(doc.to_string(), "<!--pre--><root><c/><b/></root>");
+
+        let pre = doc.before[0];
+        let text = doc.create_text("t");
+        assert_eq!(
+            pre.replace_with(&mut doc, a),
+            Err(Error::Hierarchy(a.as_node()))
+        );
+        pre.replace_with(&mut doc, text).unwrap();
+
+        let new_root = root.wrap(&mut doc, ("outer", [("v", "2")])).unwrap();
+        assert_eq!(doc.root(), new_root);
+        assert_eq!(root.parent(&doc), Some(new_root));
+        assert_eq!(
+            doc.to_string(),
+            r#"t<outer v="2"><root><c/><b/></root></outer>"#
+        );
+
+        new_root.as_node().replace_with(&mut doc, c).unwrap();
+        assert_eq!(doc.root(), c);
+        assert_eq!(c.parent(&doc), None);
+        assert_eq!(doc.to_string(), "t<c/>");
+
+        doc.compact([]);
+        assert_eq!(
+            b.wrap(&mut doc, ("Row", [("n", "2")])),
+            Err(Error::NodeNotFound(b.as_node()))
+        );
+        assert_eq!(doc.to_string(), "t<c/>");
+    }
+
+    #[test]
+    fn rename_keeps_identity() {
+        let mut doc = Document::from_str(r#"<root><potato a="b">c<d/></potato></root>"#).unwrap();
+        let potato = doc.root().children(&doc)[0];
+        let d = potato.children(&doc)[0];
+
+        potato.try_set_name(&mut doc, "veg:vegetable").unwrap();
+        assert_eq!(potato.prefix(&doc), Some("veg"));
+        assert_eq!(d.parent(&doc), Some(potato));
+        assert_eq!(
+            doc.to_string(),
+            r#"<root><veg:vegetable a="b">c<d/></veg:vegetable></root>"#
+        );
+    }
+
+    #[test]
+    fn fallible_apis() {
+        let mut doc = Document::from_str("<root><a/>text</root>").unwrap();
+        let root = doc.root();
+        let a = root.children(&doc)[0];
+        let text = root.child_nodes(&doc)[1];
+
+        assert!(matches!(
+            Document::try_new("a b"),
+            Err(Error::InvalidName(_))
+        ));
+        assert_eq!(root.attribute(&doc, "a b"), None);
+        root.remove_attribute(&mut doc, "a b");
+        assert_eq!(
+            root.try_set_attribute(&mut doc, "a b", "c"),
+            Err(Error::InvalidName("a b".into()))
+        );
+
+        a.as_node().delete(&mut doc);
+        assert_eq!(a.try_name(&doc), Err(Error::NodeNotFound(a.as_node())));
+        assert_eq!(a.try_children(&doc), Err(Error::NodeNotFound(a.as_node())));
+        assert_eq!(
+            a.try_append_text(&mut doc, "x"),
+            Err(Error::NodeNotFound(a.as_node()))
+        );
+        assert_eq!(
+            a.try_set_attribute(&mut doc, "b", "c"),
+            Err(Error::NodeNotFound(a.as_node()))
+        );
+        assert_eq!(
+            a.try_attributes(&doc),
+            Err(Error::NodeNotFound(a.as_node()))
+        );
+        assert_eq!(
+            a.try_attribute(&doc, "b"),
+            Err(Error::NodeNotFound(a.as_node()))
+        );
+        assert_eq!(
+            a.try_remove_attribute(&mut doc, "b"),
+            Err(Error::NodeNotFound(a.as_node()))
+        );
+        assert_eq!(
+            a.try_deep_clone(&mut doc),
+            Err(Error::NodeNotFound(a.as_node()))
+        );
+        let mut other = Document::new("other");
+        assert_eq!(
+            other.try_import_node(&doc, a.as_node()),
+            Err(Error::NodeNotFound(a.as_node()))
+        );
+        assert_eq!(
+            other.try_import_node(&doc, text).unwrap().parent(&other),
+            None
+        );
+        assert_eq!(
+            root.append(&mut doc, a),
+            Err(Error::NodeNotFound(a.as_node()))
+        );
+        assert_eq!(
+            root.try_remove_child(&mut doc, a.as_node()),
+            Err(Error::NotAChild(a.as_node()))
+        );
+        assert_eq!(a.unwrap(&mut doc), Err(Error::NodeNotFound(a.as_node())));
+
+        assert_eq!(text.as_text().unwrap().try_as_str(&doc), Ok("text"));
+        assert_eq!(doc.to_string(), "<root>text</root>");
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "checked-handles"))]
+    fn wrong_document() {
+        let mut doc = Document::from_str("<root><a/>text</root>").unwrap();
+        let other = Document::from_str("<root><a/>text</root>").unwrap();
+        let foreign = other.root().children(&other)[0];
+        let foreign_text = other.root().child_nodes(&other)[1].as_text().unwrap();
+
+        // Handles compare by slot alone; only using them tells the documents apart.
+        assert_eq!(foreign, doc.root().children(&doc)[0]);
+        assert_eq!(
+            foreign.try_name(&doc),
+            Err(Error::WrongDocument(foreign.as_node()))
+        );
+        assert_eq!(
+            foreign_text.try_as_str(&doc),
+            Err(Error::WrongDocument(Node::Text(foreign_text)))
+        );
+        assert_eq!(
+            doc.root().append(&mut doc, foreign),
+            Err(Error::WrongDocument(foreign.as_node()))
+        );
+        assert_eq!(
+            doc.root().try_remove_child(&mut doc, foreign.as_node()),
+            Err(Error::NotAChild(foreign.as_node()))
+        );
+        assert!(!doc.contains(foreign.as_node()));
+        assert_eq!(doc.to_string(), "<root><a/>text</root>");
+
+        // Clones share their identity, so handles stay usable in both.
+        let clone = other.clone();
+        assert_eq!(foreign.name(&clone), "a");
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "checked-handles"))]
+    #[should_panic(expected = "Node belongs to another document")]
+    fn wrong_document_panics() {
+        let doc = Document::from_str("<root><a/></root>").unwrap();
+        let other = Document::from_str("<root><a/></root>").unwrap();
+        let foreign = other.root().children(&other)[0];
+        foreign.parent(&doc);
+    }
+
     #[test]
     fn double_use() {
         let input = "<root><a/></root>";
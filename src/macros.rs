@@ -0,0 +1,329 @@
+use std::{borrow::BorrowMut, fmt::Display};
+
+use crate::{Document, Element, NewElement, Node};
+
+/// Build XML declaratively.
+///
+/// `xml!(<root ...>...</root>)` creates a new [`Document`] with the given root element, and
+/// `xml!(&mut doc, parent => ...)` appends a fragment to an element of an existing document,
+/// returning the nodes added directly under it.
+///
+/// Element and attribute names are identifiers, string literals for names that are not valid
+/// identifiers (such as `"a-b"` or `"xmlns:x"`), or `{ expr }` blocks evaluating to a string.
+/// Attribute values are literals or `{ expr }` blocks of any [`Display`] type. Text content
+/// is written the same way, and `{ ..expr }` inserts every item of an iterator of [`XmlChild`]s:
+/// the root elements of documents, which lets `xml!` calls be nested, or
+/// [`ElementBuilder`](crate::ElementBuilder)s.
+///
+/// ```
+/// use xmlem::xml;
+///
+/// let rows = [("a", 1), ("b", 2)];
+/// let doc = xml!(
+///     <table "xmlns:x"="urn:x">
+///         <caption>"Rows: " {rows.len()}</caption>
+///         {..rows.iter().map(|(name, n)| xml!(<row name={name} "x:n"={n * 100}/>))}
+///     </table>
+/// );
+/// assert_eq!(
+///     doc.to_string(),
+///     r#"<table xmlns:x="urn:x"><caption>Rows: 2</caption><row name="a" x:n="100"/><row name="b" x:n="200"/></table>"#
+/// );
+/// ```
+///
+/// ```
+/// use xmlem::{xml, Document};
+///
+/// let mut doc = Document::new("root");
+/// let root = doc.root();
+/// let tag = "item";
+/// let added = xml!(&mut doc, root => <{tag} id="1"/> "text");
+/// assert_eq!(added.len(), 2);
+/// assert_eq!(doc.to_string(), r#"<root><item id="1"/>text</root>"#);
+/// ```
+///
+/// Closing tags must repeat the name of the element they close: the same identifier or literal,
+/// or the same tokens for a `{ expr }` name, which is evaluated only once. Mismatched or missing
+/// closing tags, and documents that are not made of exactly one root element, are compile
+/// errors:
+///
+/// ```compile_fail
+/// xmlem::xml!(<a></b>);
+/// ```
+///
+/// ```compile_fail
+/// xmlem::xml!(<a/><b/>);
+/// ```
+///
+/// ```compile_fail
+/// let (a, b) = ("a", "b");
+/// xmlem::xml!(<{a}></{b}>);
+/// ```
+///
+/// # Panics
+///
+/// Panics if a name is not a valid qualified name.
+///
+/// Large templates may need a higher `#![recursion_limit]`, as every tag and piece of text is one
+/// level of macro expansion, as is each attribute of a tag that mixes identifier, literal and
+/// `{ expr }` names.
+#[macro_export]
+macro_rules! xml {
+    // The stack holds the names of the open elements, innermost first. A document's stack starts
+    // with `@doc`, and the root element is the only content allowed on top of it.
+    (@content $b:ident []) => {};
+    (@content $b:ident [@doc]) => {
+        compile_error!("xml! document has no root element");
+    };
+    (@content $b:ident [($open:tt) $($stack:tt)*]) => {
+        compile_error!(concat!("xml! element <", stringify!($open), "> is not closed"));
+    };
+    (@content $b:ident [($open:tt) $($stack:tt)*] < / $name:tt > $($rest:tt)*) => {
+        $crate::xml!(@check $open $name);
+        $b.close();
+        $crate::xml!(@after $b [$($stack)*] $($rest)*);
+    };
+    (@content $b:ident [$($stack:tt)*] < / $name:tt > $($rest:tt)*) => {
+        compile_error!(concat!(
+            "xml! closing tag </", stringify!($name), "> without an open element"
+        ));
+    };
+    // Tags whose attribute names are all identifiers or all literals take a single step.
+    (@content $b:ident [$($stack:tt)*] < $name:tt $($key:ident = $value:tt)* / > $($rest:tt)*) => {
+        $b.empty($crate::xml!(@name $name), [$((stringify!($key).to_string(), ($value).to_string())),*]);
+        $crate::xml!(@after $b [$($stack)*] $($rest)*);
+    };
+    (@content $b:ident [$($stack:tt)*] < $name:tt $($key:ident = $value:tt)* > $($rest:tt)*) => {
+        $b.open($crate::xml!(@name $name), [$((stringify!($key).to_string(), ($value).to_string())),*]);
+        $crate::xml!(@content $b [($name) $($stack)*] $($rest)*);
+    };
+    (@content $b:ident [$($stack:tt)*] < $name:tt $($key:literal = $value:tt)* / > $($rest:tt)*) => {
+        $b.empty($crate::xml!(@name $name), [$(($key.to_string(), ($value).to_string())),*]);
+        $crate::xml!(@after $b [$($stack)*] $($rest)*);
+    };
+    (@content $b:ident [$($stack:tt)*] < $name:tt $($key:literal = $value:tt)* > $($rest:tt)*) => {
+        $b.open($crate::xml!(@name $name), [$(($key.to_string(), ($value).to_string())),*]);
+        $crate::xml!(@content $b [($name) $($stack)*] $($rest)*);
+    };
+    (@content $b:ident [$($stack:tt)*] < $name:tt $($rest:tt)*) => {
+        $crate::xml!(@attrs $b [$($stack)*] $name [] $($rest)*);
+    };
+    (@content $b:ident [@doc] $($rest:tt)+) => {
+        compile_error!("xml! document content outside the root element");
+    };
+    (@content $b:ident [$($stack:tt)*] { .. $children:expr } $($rest:tt)*) => {
+        $b.children($children);
+        $crate::xml!(@content $b [$($stack)*] $($rest)*);
+    };
+    (@content $b:ident [$($stack:tt)*] $text:tt $($rest:tt)*) => {
+        $b.text($text);
+        $crate::xml!(@content $b [$($stack)*] $($rest)*);
+    };
+
+    (@attrs $b:ident [$($stack:tt)*] $name:tt [$($attr:expr),*] / > $($rest:tt)*) => {
+        $b.empty($crate::xml!(@name $name), [$($attr),*]);
+        $crate::xml!(@after $b [$($stack)*] $($rest)*);
+    };
+    (@attrs $b:ident [$($stack:tt)*] $name:tt [$($attr:expr),*] > $($rest:tt)*) => {
+        $b.open($crate::xml!(@name $name), [$($attr),*]);
+        $crate::xml!(@content $b [($name) $($stack)*] $($rest)*);
+    };
+    (@attrs $b:ident [$($stack:tt)*] $name:tt [$($attr:expr),*] $key:tt = $value:tt $($rest:tt)*) => {
+        $crate::xml!(
+            @attrs $b [$($stack)*] $name
+            [$($attr,)* ($crate::xml!(@name $key).to_string(), ($value).to_string())]
+            $($rest)*
+        );
+    };
+
+    // After an element is finished, a document whose root element it was must end.
+    (@after $b:ident [@doc] $($rest:tt)*) => {
+        $crate::xml!(@end $($rest)*);
+    };
+    (@after $b:ident [$($stack:tt)*] $($rest:tt)*) => {
+        $crate::xml!(@content $b [$($stack)*] $($rest)*);
+    };
+    (@end) => {};
+    (@end $($rest:tt)+) => {
+        compile_error!("xml! document has content after its root element");
+    };
+
+    (@check { $($open:tt)* } { $($close:tt)* }) => {
+        const _: () = assert!(
+            $crate::__private::str_eq(stringify!($($open)*), stringify!($($close)*)),
+            "xml! closing tag does not repeat the opening tag's {{ expr }} name",
+        );
+    };
+    (@check { $($open:tt)* } $close:tt) => {
+        compile_error!(concat!(
+            "xml! closing tag </", stringify!($close), "> does not match <{", stringify!($($open)*), "}>"
+        ));
+    };
+    (@check $open:tt { $($close:tt)* }) => {
+        compile_error!(concat!(
+            "xml! closing tag </{", stringify!($($close)*), "}> does not match <", stringify!($open), ">"
+        ));
+    };
+    (@check $open:tt $close:tt) => {
+        const _: () = assert!(
+            $crate::__private::str_eq($crate::xml!(@name $open), $crate::xml!(@name $close)),
+            concat!("xml! closing tag </", stringify!($close), "> does not match <", stringify!($open), ">"),
+        );
+    };
+
+    (@name $name:ident) => {
+        stringify!($name)
+    };
+    (@name $name:tt) => {
+        $name
+    };
+
+    (< $($body:tt)*) => {{
+        let mut builder = $crate::__private::MacroBuilder::document();
+        $crate::xml!(@content builder [@doc] < $($body)*);
+        builder.into_document()
+    }};
+    ($doc:expr, $parent:expr => $($body:tt)*) => {{
+        let mut builder = $crate::__private::MacroBuilder::fragment($doc, $parent);
+        $crate::xml!(@content builder [] $($body)*);
+        builder.finish()
+    }};
+}
+
+/// Compares names of opening and closing tags in constants, where `==` is not available.
+#[doc(hidden)]
+pub const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Something that [`xml!`] can insert from a `{ ..expr }` child list.
+pub trait XmlChild {
+    /// Append to `parent`, returning the new node.
+    fn append_to(self, document: &mut Document, parent: Element) -> Node;
+}
+
+/// The root element of another document is copied in.
+impl XmlChild for Document {
+    fn append_to(self, document: &mut Document, parent: Element) -> Node {
+        let node = document.import_node(&self, self.root().as_node());
+        parent.append(document, node).unwrap();
+        node
+    }
+}
+
+/// Runtime support for [`xml!`], which expands to a sequence of calls on this.
+#[doc(hidden)]
+pub struct MacroBuilder<D> {
+    document: D,
+    /// The open elements, innermost last. For fragments this starts with the parent.
+    stack: Vec<Element>,
+    /// How many elements of `stack` belong to the surrounding document.
+    base: usize,
+    added: Vec<Node>,
+}
+
+impl MacroBuilder<Document> {
+    pub fn document() -> Self {
+        MacroBuilder {
+            document: Document::new("_"),
+            stack: vec![],
+            base: 0,
+            added: vec![],
+        }
+    }
+
+    pub fn into_document(self) -> Document {
+        self.document
+    }
+}
+
+impl<'d> MacroBuilder<&'d mut Document> {
+    pub fn fragment(document: &'d mut Document, parent: Element) -> Self {
+        MacroBuilder {
+            document,
+            stack: vec![parent],
+            base: 1,
+            added: vec![],
+        }
+    }
+
+    pub fn finish(self) -> Vec<Node> {
+        self.added
+    }
+}
+
+/// The macro checks the structure of the markup, so there always is a parent below the root
+/// element, and it is the only element added without one.
+impl<D: BorrowMut<Document>> MacroBuilder<D> {
+    fn parent(&self) -> Element {
+        *self.stack.last().unwrap()
+    }
+
+    fn added(&mut self, node: Node) {
+        if self.stack.len() == self.base {
+            self.added.push(node);
+        }
+    }
+
+    fn element<const N: usize>(
+        &mut self,
+        name: impl AsRef<str>,
+        attrs: [(String, String); N],
+    ) -> Element {
+        let element = match NewElement::try_new(name.as_ref(), attrs) {
+            Ok(element) => element,
+            Err(e) => panic!("{e}"),
+        };
+        let document = self.document.borrow_mut();
+
+        if self.stack.is_empty() {
+            let root = document.root();
+            root.set_name(document, element.name);
+            document.attrs.insert(root.0, element.attrs);
+            return root;
+        }
+
+        let parent = self.parent();
+        let element = parent.append_new_element(self.document.borrow_mut(), element);
+        self.added(element.as_node());
+        element
+    }
+
+    pub fn open<const N: usize>(&mut self, name: impl AsRef<str>, attrs: [(String, String); N]) {
+        let element = self.element(name, attrs);
+        self.stack.push(element);
+    }
+
+    pub fn empty<const N: usize>(&mut self, name: impl AsRef<str>, attrs: [(String, String); N]) {
+        self.element(name, attrs);
+    }
+
+    pub fn close(&mut self) {
+        self.stack.pop();
+    }
+
+    pub fn text(&mut self, text: impl Display) {
+        let parent = self.parent();
+        let text = parent.append_text(self.document.borrow_mut(), &text.to_string());
+        self.added(text.into());
+    }
+
+    pub fn children<C: XmlChild>(&mut self, children: impl IntoIterator<Item = C>) {
+        let parent = self.parent();
+        for child in children {
+            let node = child.append_to(self.document.borrow_mut(), parent);
+            self.added(node);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Location paths such as `/merge/include[2]` or `/resources/string[@name='app']`, a small
+//! subset of XPath.
+
+use crate::{Document, Element};
+
+/// The path of `element` from the root element, or `None` if it is not in the document's tree.
+/// Each step is keyed by the first of `keys` whose value is unique among same-named siblings, by
+/// position if the name is shared, or by the name alone.
+pub(crate) fn element_path(document: &Document, element: Element, keys: &[&str]) -> Option<String> {
+    let mut steps = vec![];
+    let mut current = element;
+    loop {
+        steps.push(step(document, current, keys));
+        match current.parent(document) {
+            Some(parent) => current = parent,
+            None if current == document.root() => break,
+            None => return None,
+        }
+    }
+
+    Some(steps.iter().rev().fold(String::new(), |mut path, step| {
+        path.push('/');
+        path.push_str(step);
+        path
+    }))
+}
+
+fn step(document: &Document, element: Element, keys: &[&str]) -> String {
+    let name = element.name(document);
+    let siblings = match element.parent(document) {
+        Some(parent) => parent
+            .child_elements(document)
+            .filter(|x| x.name(document) == name)
+            .collect(),
+        None => vec![element],
+    };
+
+    for key in keys {
+        let value = match element.attribute(document, key) {
+            Some(value) => value,
+            None => continue,
+        };
+        let quote = match (value.contains('\''), value.contains('"')) {
+            (false, _) => '\'',
+            (true, false) => '"',
+            (true, true) => continue,
+        };
+        let matching = siblings
+            .iter()
+            .filter(|x| x.attribute(document, key) == Some(value))
+            .count();
+        if matching == 1 {
+            return format!("{name}[@{key}={quote}{value}{quote}]");
+        }
+    }
+
+    if siblings.len() == 1 {
+        return name.to_string();
+    }
+    let position = siblings.iter().position(|x| *x == element).unwrap();
+    format!("{name}[{}]", position + 1)
+}
+
+enum Predicate<'a> {
+    Position(usize),
+    Attribute(&'a str, &'a str),
+}
+
+/// Find the element a path from [`element_path`] refers to. If several elements match a step,
+/// the first one is used.
+pub(crate) fn resolve_path(document: &Document, path: &str) -> Option<Element> {
+    let mut rest = path.strip_prefix('/')?;
+    let mut current: Option<Element> = None;
+
+    loop {
+        let (name, predicates, remaining) = parse_step(rest)?;
+        let mut candidates = match current {
+            None => vec![document.root()],
+            Some(parent) => parent.child_elements(document).collect(),
+        };
+        candidates.retain(|x| x.name(document) == name);
+
+        for predicate in predicates {
+            candidates = match predicate {
+                Predicate::Position(n) => candidates
+                    .get(n.checked_sub(1)?)
+                    .copied()
+                    .into_iter()
+                    .collect(),
+                Predicate::Attribute(key, value) => candidates
+                    .into_iter()
+                    .filter(|x| x.attribute(document, key) == Some(value))
+                    .collect(),
+            };
+        }
+        current = Some(*candidates.first()?);
+
+        if remaining.is_empty() {
+            return current;
+        }
+        rest = remaining.strip_prefix('/')?;
+    }
+}
+
+/// Splits the first step off a path, returning its name, its predicates and the rest of the path.
+fn parse_step(input: &str) -> Option<(&str, Vec<Predicate<'_>>, &str)> {
+    let end = input.find(['[', '/']).unwrap_or(input.len());
+    let (name, mut rest) = input.split_at(end);
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut predicates = vec![];
+    while let Some(inner) = rest.strip_prefix('[') {
+        let (predicate, remaining) = match inner.strip_prefix('@') {
+            Some(attribute) => {
+                let (key, value) = attribute.split_once('=')?;
+                let quote = value.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+                let value = &value[1..];
+                let close = value.find(quote)?;
+                let remaining = value[close + 1..].strip_prefix(']')?;
+                (Predicate::Attribute(key, &value[..close]), remaining)
+            }
+            None => {
+                let (position, remaining) = inner.split_once(']')?;
+                (Predicate::Position(position.parse().ok()?), remaining)
+            }
+        };
+        predicates.push(predicate);
+        rest = remaining;
+    }
+
+    Some((name, predicates, rest))
+}
@@ -0,0 +1,182 @@
+use std::fmt::{self, Write};
+
+use cssparser::{serialize_identifier, serialize_string, ParseError};
+use selectors::parser::SelectorParseErrorKind;
+
+use crate::{
+    error::Error,
+    select::{Selector, SelectorParser},
+};
+
+/// A selector built from Rust values instead of a selector string.
+///
+/// Names and values are taken as they are, so attribute values containing quotes, brackets or
+/// backslashes need no escaping. Filters such as [`Query::attr_eq`] apply to the element the
+/// query currently ends at, and combinators such as [`Query::child`] continue the query from
+/// there. A query converts to a [`Selector`] and matches exactly like the equivalent string.
+///
+/// ```
+/// use xmlem::{Document, Query, Selector};
+///
+/// let doc = r#"<resources><string name='say "hi"'>Hi</string><string name="bye">Bye</string></resources>"#
+///     .parse::<Document>()
+///     .unwrap();
+/// let query =
+///     Query::name("resources").child(Query::name("string").attr_eq("name", r#"say "hi""#));
+/// let selector = Selector::try_from(&query).unwrap();
+/// let found = doc.root().query_selector(&doc, &selector).unwrap();
+/// assert_eq!(found.text_content(&doc), "Hi");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    css: String,
+    /// Whether the query uses `:has()` anywhere, which another `:has()` may not contain.
+    has: bool,
+}
+
+impl Query {
+    /// Elements with exactly the given name, including any prefix, as in `x:Row`.
+    pub fn name(name: &str) -> Self {
+        let mut css = String::new();
+        identifier(&mut css, name);
+        Query { css, has: false }
+    }
+
+    /// Any element.
+    pub fn any() -> Self {
+        Query {
+            css: "*".to_string(),
+            has: false,
+        }
+    }
+
+    /// Elements with the given attribute, whatever its value.
+    pub fn attr(mut self, name: &str) -> Self {
+        self.css.push('[');
+        identifier(&mut self.css, name);
+        self.css.push(']');
+        self
+    }
+
+    /// Elements whose attribute equals `value`.
+    pub fn attr_eq(self, name: &str, value: &str) -> Self {
+        self.attr_op(name, "=", value)
+    }
+
+    /// Elements whose attribute starts with `value`.
+    pub fn attr_starts_with(self, name: &str, value: &str) -> Self {
+        self.attr_op(name, "^=", value)
+    }
+
+    /// Elements whose attribute ends with `value`.
+    pub fn attr_ends_with(self, name: &str, value: &str) -> Self {
+        self.attr_op(name, "$=", value)
+    }
+
+    /// Elements whose attribute contains `value`.
+    pub fn attr_contains(self, name: &str, value: &str) -> Self {
+        self.attr_op(name, "*=", value)
+    }
+
+    /// Elements with the given `id` attribute.
+    pub fn id(self, id: &str) -> Self {
+        self.attr_op("id", "=", id)
+    }
+
+    /// Elements with `class` among the whitespace-separated words of their `class` attribute.
+    pub fn class(self, class: &str) -> Self {
+        self.attr_op("class", "~=", class)
+    }
+
+    /// Elements not matched by `query`.
+    pub fn not(mut self, query: Query) -> Self {
+        write!(self.css, ":not({})", query.css).unwrap();
+        self.has |= query.has;
+        self
+    }
+
+    /// Elements with a descendant at which `query` starts, as in `:has()`.
+    ///
+    /// Fails with [`Error::NestedHas`] if `query` uses `has` itself, including inside
+    /// [`Query::not`] or further along a combinator, since `:has()` cannot be nested.
+    ///
+    /// ```
+    /// use xmlem::{Error, Query};
+    ///
+    /// let plurals = Query::name("plurals").has(Query::name("item")).unwrap();
+    /// assert_eq!(plurals.to_string(), "plurals:has(item)");
+    /// assert_eq!(
+    ///     Query::name("resources").has(plurals),
+    ///     Err(Error::NestedHas("plurals:has(item)".to_string()))
+    /// );
+    /// ```
+    pub fn has(mut self, query: Query) -> Result<Self, Error> {
+        if query.has {
+            return Err(Error::NestedHas(query.css));
+        }
+        write!(self.css, ":has({})", query.css).unwrap();
+        self.has = true;
+        Ok(self)
+    }
+
+    /// Elements matched by `query` that are children of the current elements.
+    pub fn child(self, query: Query) -> Self {
+        self.combine(" > ", query)
+    }
+
+    /// Elements matched by `query` that are descendants of the current elements.
+    pub fn descendant(self, query: Query) -> Self {
+        self.combine(" ", query)
+    }
+
+    /// Elements matched by `query` that immediately follow the current elements.
+    pub fn next_sibling(self, query: Query) -> Self {
+        self.combine(" + ", query)
+    }
+
+    /// Elements matched by `query` that follow the current elements as siblings.
+    pub fn subsequent_sibling(self, query: Query) -> Self {
+        self.combine(" ~ ", query)
+    }
+
+    fn attr_op(mut self, name: &str, operator: &str, value: &str) -> Self {
+        self.css.push('[');
+        identifier(&mut self.css, name);
+        self.css.push_str(operator);
+        serialize_string(value, &mut self.css).unwrap();
+        self.css.push(']');
+        self
+    }
+
+    fn combine(mut self, combinator: &str, query: Query) -> Self {
+        self.css.push_str(combinator);
+        self.css.push_str(&query.css);
+        self.has |= query.has;
+        self
+    }
+}
+
+/// Writes `name` as a CSS identifier. The empty name has no identifier, and is written as `-`,
+/// which is not a valid XML name either.
+fn identifier(css: &mut String, name: &str) {
+    if name.is_empty() {
+        css.push_str(r"\-");
+    }
+    serialize_identifier(name, css).unwrap();
+}
+
+/// Shows the query as the equivalent selector string.
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.css)
+    }
+}
+
+/// Parses the equivalent selector string, returning the parser's error if it is rejected.
+impl<'a> TryFrom<&'a Query> for Selector {
+    type Error = ParseError<'a, SelectorParseErrorKind<'a>>;
+
+    fn try_from(query: &'a Query) -> Result<Self, Self::Error> {
+        SelectorParser::new().parse(&query.css)
+    }
+}
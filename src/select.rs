@@ -1,17 +1,21 @@
-use std::borrow::Borrow;
+use std::borrow::{Borrow, Cow};
+use std::collections::HashMap;
+use std::{fmt, sync::Arc};
 
-use cssparser::{CowRcStr, ParseError, SourceLocation};
+use cssparser::{CowRcStr, ParseError, SourceLocation, Token};
 use qname::QName;
 use selectors::attr::{AttrSelectorOperation, CaseSensitivity, NamespaceConstraint};
+use selectors::bloom::{BloomFilter, BLOOM_HASH_MASK};
 use selectors::context::{IgnoreNthChildForInvalidation, NeedsSelectorFlags, QuirksMode};
 use selectors::parser::{
-    NonTSPseudoClass, ParseRelative, Parser, Selector as GenericSelector, SelectorImpl,
-    SelectorList,
+    AncestorHashes, Combinator, Component, NonTSPseudoClass, ParseRelative, Parser,
+    Selector as GenericSelector, SelectorImpl, SelectorIter, SelectorList,
 };
 use selectors::parser::{PseudoElement, SelectorParseErrorKind};
 use selectors::{self, matching, NthIndexCache, OpaqueElement};
 
-use crate::{Document, Element};
+use crate::iter::NodeEdge;
+use crate::{Document, Element, Node};
 
 #[derive(Debug, Clone)]
 pub struct Selectors;
@@ -46,7 +50,83 @@ impl Borrow<String> for Value {
     }
 }
 
-impl NonTSPseudoClass for Value {
+/// The hash of names and values in the ancestor Bloom filter, 32-bit FNV-1a.
+fn hash(s: &str) -> u32 {
+    s.bytes()
+        .fold(0x811c9dc5, |h, b| (h ^ b as u32).wrapping_mul(0x01000193))
+}
+
+/// The pseudo-classes that depend on the document rather than on the structure of the tree,
+/// which the selectors crate matches itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PseudoClass {
+    /// `:lang()` with its language ranges.
+    Lang(Vec<String>),
+    /// A pseudo-class registered on a [`SelectorParser`], with its arguments.
+    Custom(String, Vec<String>, Matcher),
+}
+
+type MatchFn = dyn Fn(&Document, Element, &[String]) -> bool + Send + Sync;
+
+/// A user-defined pseudo-class predicate. Predicates are equal if they are the same closure.
+#[derive(Clone)]
+pub struct Matcher {
+    function: Arc<MatchFn>,
+    takes_arguments: bool,
+}
+
+impl PartialEq for Matcher {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+impl Eq for Matcher {}
+
+impl fmt::Debug for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Matcher")
+            .field("takes_arguments", &self.takes_arguments)
+            .finish_non_exhaustive()
+    }
+}
+
+impl cssparser::ToCss for PseudoClass {
+    fn to_css<W>(&self, dest: &mut W) -> std::fmt::Result
+    where
+        W: std::fmt::Write,
+    {
+        match self {
+            PseudoClass::Lang(ranges) => {
+                dest.write_str(":lang(")?;
+                for (i, range) in ranges.iter().enumerate() {
+                    if i > 0 {
+                        dest.write_str(", ")?;
+                    }
+                    cssparser::serialize_string(range, dest)?;
+                }
+                dest.write_str(")")
+            }
+            PseudoClass::Custom(name, arguments, matcher) => {
+                dest.write_str(":")?;
+                cssparser::serialize_identifier(name, dest)?;
+                if matcher.takes_arguments {
+                    dest.write_str("(")?;
+                    for (i, argument) in arguments.iter().enumerate() {
+                        if i > 0 {
+                            dest.write_str(", ")?;
+                        }
+                        cssparser::serialize_string(argument, dest)?;
+                    }
+                    dest.write_str(")")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl NonTSPseudoClass for PseudoClass {
     type Impl = Selectors;
 
     fn is_active_or_hover(&self) -> bool {
@@ -58,7 +138,36 @@ impl NonTSPseudoClass for Value {
     }
 }
 
-impl PseudoElement for Value {
+/// The pseudo-elements that select nodes other than elements, for
+/// [`Selector::select_nodes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodePseudoElement {
+    /// `::text`, the text and CDATA children of an element.
+    Text,
+    /// `::comment`, the comment children of an element.
+    Comment,
+    /// `::attr(name)`, the value of an attribute.
+    Attr(QName),
+}
+
+impl cssparser::ToCss for NodePseudoElement {
+    fn to_css<W>(&self, dest: &mut W) -> std::fmt::Result
+    where
+        W: std::fmt::Write,
+    {
+        match self {
+            NodePseudoElement::Text => dest.write_str("::text"),
+            NodePseudoElement::Comment => dest.write_str("::comment"),
+            NodePseudoElement::Attr(name) => {
+                dest.write_str("::attr(")?;
+                cssparser::serialize_string(name.prefixed_name(), dest)?;
+                dest.write_str(")")
+            }
+        }
+    }
+}
+
+impl PseudoElement for NodePseudoElement {
     type Impl = Selectors;
 }
 
@@ -71,27 +180,35 @@ impl SelectorImpl for Selectors {
     type NamespacePrefix = Value;
     type BorrowedNamespaceUrl = String;
     type BorrowedLocalName = String;
-    type NonTSPseudoClass = Value;
-    type PseudoElement = Value;
+    type NonTSPseudoClass = PseudoClass;
+    type PseudoElement = NodePseudoElement;
+}
+
+/// Identifies an element by the address of its value, which is stable while the document is
+/// borrowed.
+fn opaque(document: &Document, element: Element) -> OpaqueElement {
+    OpaqueElement::new(element.value(document))
 }
 
 #[derive(Debug, Clone)]
-struct ElementRef<'a> {
+struct SelectorElement<'a> {
     document: &'a Document,
     element: Element,
+    /// Whether type selectors match local names, as set by [`SelectorParser::local_names`].
+    local_names: bool,
 }
 
-impl selectors::Element for ElementRef<'_> {
+impl selectors::Element for SelectorElement<'_> {
     type Impl = Selectors;
 
     fn opaque(&self) -> OpaqueElement {
-        OpaqueElement::new(&self.element.0)
+        opaque(self.document, self.element)
     }
 
     fn parent_element(&self) -> Option<Self> {
-        self.element.parent(self.document).map(|e| ElementRef {
-            document: self.document,
+        self.element.parent(self.document).map(|e| SelectorElement {
             element: e,
+            ..*self
         })
     }
 
@@ -110,18 +227,18 @@ impl selectors::Element for ElementRef<'_> {
     fn prev_sibling_element(&self) -> Option<Self> {
         self.element
             .prev_sibling_element(self.document)
-            .map(|e| ElementRef {
-                document: self.document,
+            .map(|e| SelectorElement {
                 element: e,
+                ..*self
             })
     }
 
     fn next_sibling_element(&self) -> Option<Self> {
         self.element
             .next_sibling_element(self.document)
-            .map(|e| ElementRef {
-                document: self.document,
+            .map(|e| SelectorElement {
                 element: e,
+                ..*self
             })
     }
 
@@ -129,16 +246,24 @@ impl selectors::Element for ElementRef<'_> {
         false
     }
 
+    /// Type selectors match the full name, given with an escaped colon as in `x\:Row`. With
+    /// [`SelectorParser::local_names`], they also match the local part in any namespace.
     fn has_local_name(&self, local_name: &<Self::Impl as SelectorImpl>::BorrowedLocalName) -> bool {
-        self.element.name(self.document) == local_name
+        let name = self.element.qname(self.document);
+        name.prefixed_name() == local_name || self.local_names && name.local_part() == local_name
     }
 
+    /// Namespaces are identified by their prefix. With [`SelectorParser::local_names`], `|name`
+    /// matches unprefixed names.
     fn has_namespace(&self, ns: &<Self::Impl as SelectorImpl>::BorrowedNamespaceUrl) -> bool {
-        self.element.prefix(self.document) == Some(ns)
+        match self.element.prefix(self.document) {
+            Some(prefix) => prefix == ns,
+            None => self.local_names && ns.is_empty(),
+        }
     }
 
     fn is_same_type(&self, other: &Self) -> bool {
-        self.element.name(self.document) == other.element.name(other.document)
+        self.element.qname(self.document) == other.element.qname(other.document)
     }
 
     fn attr_matches(
@@ -149,27 +274,38 @@ impl selectors::Element for ElementRef<'_> {
     ) -> bool {
         let attrs = self.element.attributes(self.document);
 
+        // Names that are not valid QNames, such as `[\-]`, match no attribute.
         let qname = match ns {
-            NamespaceConstraint::Any => QName::new_unchecked(&local_name.0),
-            NamespaceConstraint::Specific(ns) if ns == &"" => QName::new_unchecked(&local_name.0),
-            NamespaceConstraint::Specific(ns) => {
-                QName::new_unchecked(&format!("{}:{}", ns, local_name.0))
-            }
+            NamespaceConstraint::Any => QName::new(&local_name.0),
+            NamespaceConstraint::Specific(ns) if ns.is_empty() => QName::new(&local_name.0),
+            NamespaceConstraint::Specific(ns) => QName::new(&format!("{}:{}", ns, local_name.0)),
         };
 
-        if let Some(val) = attrs.get(&qname) {
-            operation.eval_str(val)
-        } else {
-            false
+        match qname.ok().and_then(|qname| attrs.get(&qname)) {
+            Some(val) => operation.eval_str(val),
+            None => false,
         }
     }
 
     fn match_non_ts_pseudo_class(
         &self,
-        _pc: &<Self::Impl as SelectorImpl>::NonTSPseudoClass,
+        pc: &<Self::Impl as SelectorImpl>::NonTSPseudoClass,
         _context: &mut selectors::context::MatchingContext<Self::Impl>,
     ) -> bool {
-        false
+        match pc {
+            PseudoClass::Lang(ranges) => {
+                let lang = std::iter::once(self.element)
+                    .chain(self.element.ancestors(self.document))
+                    .find_map(|e| e.attribute(self.document, "xml:lang"));
+                match lang {
+                    Some(lang) => ranges.iter().any(|range| lang_matches(range, lang)),
+                    None => false,
+                }
+            }
+            PseudoClass::Custom(_, arguments, matcher) => {
+                (matcher.function)(self.document, self.element, arguments)
+            }
+        }
     }
 
     fn match_pseudo_element(
@@ -204,12 +340,9 @@ impl selectors::Element for ElementRef<'_> {
         name: &<Self::Impl as SelectorImpl>::Identifier,
         case_sensitivity: CaseSensitivity,
     ) -> bool {
-        match self.element.attribute(self.document, "class") {
-            Some(x) => x
-                .split_whitespace()
-                .any(|x| case_sensitivity.eq(x.as_bytes(), name.0.as_bytes())),
-            None => false,
-        }
+        self.element
+            .attribute_tokens(self.document, "class")
+            .any(|x| case_sensitivity.eq(x.as_bytes(), name.0.as_bytes()))
     }
 
     fn imported_part(
@@ -223,8 +356,21 @@ impl selectors::Element for ElementRef<'_> {
         false
     }
 
+    /// Comments and whitespace do not count as content, as in Selectors Level 4.
     fn is_empty(&self) -> bool {
-        self.element.children(self.document).is_empty()
+        let is_space = |text: &str| {
+            text.chars()
+                .all(|c| matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0C'))
+        };
+        self.element
+            .child_nodes(self.document)
+            .iter()
+            .all(|node| match node {
+                Node::Element(_) => false,
+                Node::Text(t) => is_space(t.as_str(self.document)),
+                Node::CDataSection(t) => is_space(t.as_str(self.document)),
+                _ => true,
+            })
     }
 
     fn is_root(&self) -> bool {
@@ -233,76 +379,671 @@ impl selectors::Element for ElementRef<'_> {
 
     fn first_element_child(&self) -> Option<Self> {
         self.element
-            .children(&self.document)
-            .into_iter()
+            .child_elements(self.document)
             .next()
             .map(|child| Self {
                 element: child,
-                document: self.document,
+                ..*self
             })
     }
 
     fn apply_selector_flags(&self, _flags: matching::ElementSelectorFlags) {}
 }
 
-struct TheParser;
+/// Parses selectors with additional, user-defined pseudo-classes.
+///
+/// Pseudo-classes are registered by name, which is matched ignoring ASCII case, with a predicate
+/// on the element being matched. Those registered with
+/// [`pseudo_class_with_arguments`](SelectorParser::pseudo_class_with_arguments) are written as
+/// functions, and receive their comma-separated arguments: the value of an identifier or string,
+/// or the source text of anything else. Built-in pseudo-classes cannot be replaced.
+///
+/// ```
+/// use xmlem::{Document, SelectorParser};
+///
+/// let parser = SelectorParser::new()
+///     .pseudo_class("translatable", |doc, e| e.attribute(doc, "translatable") != Some("false"))
+///     .pseudo_class_with_arguments("contains", |doc, e, args| {
+///         args.iter().all(|arg| e.text_content(doc).contains(arg.as_str()))
+///     });
+/// let selector = parser.parse(r#"string:translatable:contains("mlem", x)"#).unwrap();
+///
+/// let doc = r#"<resources><string>mlem x</string><string translatable="false">mlem x</string><string>mlem</string></resources>"#
+///     .parse::<Document>()
+///     .unwrap();
+/// assert_eq!(doc.root().query_selector_all(&doc, &selector).len(), 1);
+/// ```
+#[derive(Clone, Default)]
+pub struct SelectorParser {
+    pseudo_classes: HashMap<String, Matcher>,
+    local_names: bool,
+}
 
-impl<'i> Parser<'i> for TheParser {
+impl SelectorParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match type selectors against the local part of names in any namespace, so that `item`
+    /// also matches `<x:item>`, and namespace selectors against prefixes, so that `x|item`
+    /// matches `<x:item>` and `|item` only an unprefixed `<item>`.
+    ///
+    /// Off by default, where a type selector must equal the full name, as in `x\:item`.
+    ///
+    /// ```
+    /// use xmlem::{Document, Selector, SelectorParser};
+    ///
+    /// let doc = r#"<merge xmlns:latin="urn:latin"><latin:include/><include/></merge>"#
+    ///     .parse::<Document>()
+    ///     .unwrap();
+    /// let count = |selector: &Selector| doc.root().query_selector_all(&doc, selector).len();
+    /// assert_eq!(count(&Selector::new("include").unwrap()), 1);
+    /// assert_eq!(count(&Selector::new(r"latin\:include").unwrap()), 1);
+    ///
+    /// let parser = SelectorParser::new().local_names(true);
+    /// assert_eq!(count(&parser.parse("include").unwrap()), 2);
+    /// assert_eq!(count(&parser.parse("latin|include").unwrap()), 1);
+    /// assert_eq!(count(&parser.parse("|include").unwrap()), 1);
+    /// ```
+    pub fn local_names(mut self, enabled: bool) -> Self {
+        self.local_names = enabled;
+        self
+    }
+
+    /// Register a pseudo-class written without arguments, as in `:name`.
+    pub fn pseudo_class(
+        self,
+        name: &str,
+        predicate: impl Fn(&Document, Element) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.register(name, false, Arc::new(move |doc, e, _| predicate(doc, e)))
+    }
+
+    /// Register a pseudo-class written as a function, as in `:name(a, b)`.
+    pub fn pseudo_class_with_arguments(
+        self,
+        name: &str,
+        predicate: impl Fn(&Document, Element, &[String]) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.register(name, true, Arc::new(predicate))
+    }
+
+    fn register(mut self, name: &str, takes_arguments: bool, function: Arc<MatchFn>) -> Self {
+        let matcher = Matcher {
+            function,
+            takes_arguments,
+        };
+        self.pseudo_classes
+            .insert(name.to_ascii_lowercase(), matcher);
+        self
+    }
+
+    pub fn parse<'i>(
+        &self,
+        s: &'i str,
+    ) -> Result<Selector, ParseError<'i, SelectorParseErrorKind<'i>>> {
+        let mut input = cssparser::ParserInput::new(s);
+        match SelectorList::parse(
+            self,
+            &mut cssparser::Parser::new(&mut input),
+            ParseRelative::No,
+        ) {
+            Ok(list) => Ok(Selector(
+                list.0
+                    .into_iter()
+                    .map(|selector| SelectorInner::new(selector, self.local_names))
+                    .collect(),
+            )),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn custom(&self, name: &str, takes_arguments: bool) -> Option<&Matcher> {
+        self.pseudo_classes
+            .get(&name.to_ascii_lowercase())
+            .filter(|m| m.takes_arguments == takes_arguments)
+    }
+}
+
+impl fmt::Debug for SelectorParser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SelectorParser")
+            .field("pseudo_classes", &self.pseudo_classes.keys())
+            .field("local_names", &self.local_names)
+            .finish()
+    }
+}
+
+impl<'i> Parser<'i> for SelectorParser {
     type Impl = Selectors;
     type Error = SelectorParseErrorKind<'i>;
 
+    fn parse_nth_child_of(&self) -> bool {
+        true
+    }
+
+    fn parse_is_and_where(&self) -> bool {
+        true
+    }
+
+    fn parse_has(&self) -> bool {
+        true
+    }
+
     fn parse_non_ts_pseudo_class(
         &self,
         location: SourceLocation,
         name: CowRcStr<'i>,
-    ) -> Result<Value, ParseError<'i, SelectorParseErrorKind<'i>>> {
+    ) -> Result<PseudoClass, ParseError<'i, SelectorParseErrorKind<'i>>> {
+        if let Some(matcher) = self.custom(&name, false) {
+            return Ok(PseudoClass::Custom(
+                name.to_ascii_lowercase(),
+                vec![],
+                matcher.clone(),
+            ));
+        }
         Err(
             location.new_custom_error(SelectorParseErrorKind::UnsupportedPseudoClassOrElement(
                 name,
             )),
         )
     }
+
+    fn namespace_for_prefix(&self, prefix: &Value) -> Option<String> {
+        Some(prefix.0.clone())
+    }
+
+    fn parse_pseudo_element(
+        &self,
+        location: SourceLocation,
+        name: CowRcStr<'i>,
+    ) -> Result<NodePseudoElement, ParseError<'i, SelectorParseErrorKind<'i>>> {
+        if name.eq_ignore_ascii_case("text") {
+            return Ok(NodePseudoElement::Text);
+        }
+        if name.eq_ignore_ascii_case("comment") {
+            return Ok(NodePseudoElement::Comment);
+        }
+        Err(
+            location.new_custom_error(SelectorParseErrorKind::UnsupportedPseudoClassOrElement(
+                name,
+            )),
+        )
+    }
+
+    /// `::attr()` takes a name, which may be prefixed as `x|name` or given as a string.
+    fn parse_functional_pseudo_element<'t>(
+        &self,
+        name: CowRcStr<'i>,
+        arguments: &mut cssparser::Parser<'i, 't>,
+    ) -> Result<NodePseudoElement, ParseError<'i, SelectorParseErrorKind<'i>>> {
+        if !name.eq_ignore_ascii_case("attr") {
+            return Err(arguments.new_custom_error(
+                SelectorParseErrorKind::UnsupportedPseudoClassOrElement(name),
+            ));
+        }
+
+        let location = arguments.current_source_location();
+        let mut attribute = arguments.expect_ident_or_string()?.to_string();
+        if arguments.try_parse(|i| i.expect_delim('|')).is_ok() {
+            attribute = format!("{attribute}:{}", arguments.expect_ident()?);
+        }
+        match attribute.parse() {
+            Ok(attribute) => Ok(NodePseudoElement::Attr(attribute)),
+            Err(_) => Err(location.new_custom_error(
+                SelectorParseErrorKind::UnsupportedPseudoClassOrElement(name),
+            )),
+        }
+    }
+
+    fn parse_non_ts_functional_pseudo_class<'t>(
+        &self,
+        name: CowRcStr<'i>,
+        arguments: &mut cssparser::Parser<'i, 't>,
+    ) -> Result<PseudoClass, ParseError<'i, SelectorParseErrorKind<'i>>> {
+        if name.eq_ignore_ascii_case("lang") {
+            let ranges = arguments
+                .parse_comma_separated(|input| Ok(input.expect_ident_or_string()?.to_string()))?;
+            return Ok(PseudoClass::Lang(ranges));
+        }
+        if let Some(matcher) = self.custom(&name, true) {
+            let arguments = arguments.parse_comma_separated(|input| {
+                let start = input.position();
+                let token = input.next()?.clone();
+                match token {
+                    Token::Ident(s) | Token::QuotedString(s) if input.is_exhausted() => {
+                        Ok(s.to_string())
+                    }
+                    _ => {
+                        while input.next().is_ok() {}
+                        Ok(input.slice_from(start).trim().to_string())
+                    }
+                }
+            })?;
+            return Ok(PseudoClass::Custom(
+                name.to_ascii_lowercase(),
+                arguments,
+                matcher.clone(),
+            ));
+        }
+        Err(
+            arguments.new_custom_error(SelectorParseErrorKind::UnsupportedPseudoClassOrElement(
+                name,
+            )),
+        )
+    }
+}
+
+/// Whether a language tag matches a language range, using the extended filtering of RFC 4647
+/// that `:lang()` is defined with. `*` matches any subtag, and subtags of the tag may be
+/// skipped, so `de-*-DE` matches `de-Latn-DE`.
+fn lang_matches(range: &str, tag: &str) -> bool {
+    if range.is_empty() {
+        return tag.is_empty();
+    }
+
+    let mut range = range.split('-');
+    let mut tag = tag.split('-');
+    let first = range.next().unwrap_or_default();
+    match tag.next() {
+        Some(subtag) if first == "*" || first.eq_ignore_ascii_case(subtag) => {}
+        _ => return false,
+    }
+
+    for subtag in range.filter(|s| *s != "*") {
+        loop {
+            match tag.next() {
+                Some(t) if t.eq_ignore_ascii_case(subtag) => break,
+                Some(t) if t.len() > 1 => continue,
+                _ => return false,
+            }
+        }
+    }
+    true
 }
 
 #[derive(Debug, Clone)]
-struct SelectorInner(GenericSelector<Selectors>);
+struct SelectorInner {
+    selector: GenericSelector<Selectors>,
+    /// Names an element's ancestors must have for it to match, checked against the
+    /// [`MatchingCaches`] Bloom filter.
+    hashes: AncestorHashes,
+    local_names: bool,
+}
+
+/// State shared by the matching of selectors against the elements of one traversal.
+///
+/// The `:nth-*` pseudo-classes cache sibling positions, so that each set of siblings is counted
+/// once instead of once per element. A traversal that reports the elements it enters and leaves
+/// also gets a Bloom filter of the ancestors' names, ids, classes and attributes, which rejects
+/// most elements that cannot match a descendant or child combinator without walking up the tree.
+#[derive(Default)]
+pub(crate) struct MatchingCaches {
+    nth_index: NthIndexCache,
+    ancestors: Option<Box<BloomFilter>>,
+}
+
+impl MatchingCaches {
+    /// Caches for matching `selector` in a traversal starting at `root`, with a Bloom filter
+    /// holding its ancestors if the selector has any use for one.
+    pub(crate) fn for_subtree(document: &Document, root: Element, selector: &Selector) -> Self {
+        let ancestors = selector
+            .0
+            .iter()
+            .any(|s| s.hashes.packed_hashes[0] != 0)
+            .then(|| {
+                let mut filter = Box::<BloomFilter>::default();
+                for ancestor in root.ancestors(document) {
+                    for_each_hash(document, ancestor, |hash| filter.insert_hash(hash));
+                }
+                filter
+            });
+        MatchingCaches {
+            nth_index: NthIndexCache::default(),
+            ancestors,
+        }
+    }
+
+    /// Follows a traversal into and out of elements, keeping the Bloom filter up to date.
+    /// Elements without child elements are never an ancestor, and are left out.
+    pub(crate) fn visit(&mut self, document: &Document, edge: NodeEdge) {
+        let Some(filter) = &mut self.ancestors else {
+            return;
+        };
+        let is_parent = |e: Element| {
+            e.child_nodes(document)
+                .iter()
+                .any(|n| matches!(n, Node::Element(_)))
+        };
+        match edge {
+            NodeEdge::Enter(Node::Element(e)) if is_parent(e) => {
+                for_each_hash(document, e, |hash| filter.insert_hash(hash))
+            }
+            NodeEdge::Leave(Node::Element(e)) if is_parent(e) => {
+                for_each_hash(document, e, |hash| filter.remove_hash(hash))
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Debug for MatchingCaches {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MatchingCaches")
+            .field("ancestors", &self.ancestors.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Collects up to four hashes of what the compound selectors for ancestors of the matched element
+/// require, for [`AncestorHashes`]. Unlike [`AncestorHashes::new`], type selectors are included
+/// whatever their case, since XML names are case-sensitive, and so is the first compound of an
+/// `:is()` or `:where()` argument in an ancestor's compound, which that ancestor itself must
+/// match. `ancestor` is whether the first compound of `iter` is for an ancestor. Returns false
+/// once there are four.
+fn collect_ancestor_hashes(
+    mut iter: SelectorIter<Selectors>,
+    mut ancestor: bool,
+    hashes: &mut Vec<u32>,
+) -> bool {
+    // Compounds after sibling combinators are for siblings of the element or of its ancestors.
+    loop {
+        for component in iter.by_ref().filter(|_| ancestor) {
+            let hash = match component {
+                Component::LocalName(name) => hash(&name.name.0),
+                Component::DefaultNamespace(url) | Component::Namespace(_, url) => hash(url),
+                Component::ID(value) | Component::Class(value) => hash(&value.0),
+                Component::AttributeInNoNamespaceExists { local_name, .. }
+                | Component::AttributeInNoNamespace { local_name, .. } => hash(&local_name.0),
+                Component::AttributeOther(attr) => hash(&attr.local_name.0),
+                // Only a single selector must match, rather than any of several.
+                Component::Is(list) | Component::Where(list) if list.len() == 1 => {
+                    if !collect_ancestor_hashes(list[0].iter(), true, hashes) {
+                        return false;
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            hashes.push(hash & BLOOM_HASH_MASK);
+            if hashes.len() == 4 {
+                return false;
+            }
+        }
+        match iter.next_sequence() {
+            None => return true,
+            Some(combinator) => {
+                ancestor = matches!(combinator, Combinator::Child | Combinator::Descendant)
+            }
+        }
+    }
+}
+
+/// Calls `f` with the hash of everything about `element` a selector's [`AncestorHashes`] may
+/// require of an ancestor: both forms of its name, its prefix, its id, classes and attribute
+/// names.
+fn for_each_hash(document: &Document, element: Element, mut f: impl FnMut(u32)) {
+    let name = element.qname(document);
+    f(hash(name.local_part()));
+    if name.namespace().is_some() {
+        f(hash(name.prefixed_name()));
+    }
+    f(hash(name.namespace().unwrap_or_default()));
+    for (name, value) in element.attributes(document) {
+        f(hash(name.local_part()));
+        if name.namespace().is_some() {
+            f(hash(name.prefixed_name()));
+        }
+        match name.prefixed_name() {
+            "id" => f(hash(value)),
+            "class" => value.split_whitespace().for_each(|class| f(hash(class))),
+            _ => {}
+        }
+    }
+}
 
+/// A CSS selector list, as in Selectors Level 4.
+///
+/// Supports `:is()`, `:where()`, `:not()` and `:has()` with selector lists, the `:nth-*`
+/// pseudo-classes including `:nth-child(An+B of S)`, `:lang()` against inherited `xml:lang`
+/// attributes, and the `i` and `s` flags on attribute selectors. Type selectors match the full
+/// name, so `item` does not match `<x:item>` but `x\:item` does; see
+/// [`SelectorParser::local_names`] to match by local name and prefix instead. Namespace
+/// prefixes of attributes are matched literally: `[x|id]` matches the attribute `x:id`.
+///
+/// The `::text`, `::comment` and `::attr(name)` pseudo-elements select other kinds of nodes with
+/// [`Selector::select_nodes`]; elements never match them.
+///
+/// ```
+/// use xmlem::{Document, Selector};
+///
+/// let doc = r#"<keyboard xml:lang="se"><row><key code="q"/></row><row/></keyboard>"#
+///     .parse::<Document>()
+///     .unwrap();
+/// let selector = Selector::new("row:has(> key):lang(se), row:empty").unwrap();
+/// assert_eq!(doc.root().query_selector_all(&doc, &selector).len(), 2);
+/// ```
 #[derive(Debug, Clone)]
 pub struct Selector(Vec<SelectorInner>);
 
 impl Selector {
-    pub fn new(s: &str) -> Result<Selector, ParseError<SelectorParseErrorKind>> {
-        let mut input = cssparser::ParserInput::new(s);
-        match SelectorList::parse(
-            &TheParser,
-            &mut cssparser::Parser::new(&mut input),
-            ParseRelative::No,
-        ) {
-            Ok(list) => Ok(Selector(list.0.into_iter().map(SelectorInner).collect())),
-            Err(e) => Err(e),
-        }
+    /// Parse a selector list with the built-in pseudo-classes only. Use a [`SelectorParser`]
+    /// to add others.
+    pub fn new(s: &str) -> Result<Selector, ParseError<'_, SelectorParseErrorKind<'_>>> {
+        SelectorParser::new().parse(s)
     }
 
     /// Returns whether the given element matches this selector.
     #[inline]
     pub fn matches(&self, document: &Document, element: Element) -> bool {
-        let mut cache = NthIndexCache::default();
+        self.matches_in_scope(document, element, None)
+    }
+
+    /// Like [`Selector::matches`], with `:scope` matching `scope` instead of the root element.
+    pub(crate) fn matches_in_scope(
+        &self,
+        document: &Document,
+        element: Element,
+        scope: Option<Element>,
+    ) -> bool {
+        self.matches_with(document, element, scope, &mut MatchingCaches::default())
+    }
+
+    /// Like [`Selector::matches_in_scope`], reusing `caches` from the previous elements of a
+    /// traversal.
+    pub(crate) fn matches_with(
+        &self,
+        document: &Document,
+        element: Element,
+        scope: Option<Element>,
+        caches: &mut MatchingCaches,
+    ) -> bool {
+        self.0
+            .iter()
+            .any(|s| s.pseudo_element().is_none() && s.matches(document, element, scope, caches))
+    }
+
+    /// Select the elements below `scope` matching this selector, and the text, comments and
+    /// attributes selected by its `::text`, `::comment` and `::attr(name)` pseudo-elements.
+    ///
+    /// Results are in document order without duplicates. The nodes of an element follow it in
+    /// the order of the element itself, its attributes and then its children.
+    ///
+    /// ```
+    /// use xmlem::{Document, Selector};
+    ///
+    /// let doc = r#"<resources><string name="app">Mlem<!--title--></string><string name="hi">Hi</string></resources>"#
+    ///     .parse::<Document>()
+    ///     .unwrap();
+    /// let selector = Selector::new("string::attr(name), string::text, ::comment").unwrap();
+    /// let values = selector
+    ///     .select_nodes(&doc, doc.root())
+    ///     .iter()
+    ///     .map(|n| n.text(&doc))
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(values, ["app", "Mlem", "title", "hi", "Hi"]);
+    /// ```
+    pub fn select_nodes<'d>(
+        &self,
+        document: &'d Document,
+        scope: Element,
+    ) -> Vec<SelectedNode<'d>> {
+        let mut nodes = vec![];
+        let mut caches = MatchingCaches::for_subtree(document, scope, self);
+        for edge in scope.traverse(document) {
+            let element = match edge {
+                NodeEdge::Enter(Node::Element(e)) if e != scope => e,
+                _ => {
+                    caches.visit(document, edge);
+                    continue;
+                }
+            };
+            let (mut itself, mut text, mut comments) = (false, false, false);
+            let mut attributes = vec![];
+            for selector in &self.0 {
+                let pseudo = selector.pseudo_element();
+                let wanted = match pseudo {
+                    None => !itself,
+                    Some(NodePseudoElement::Text) => !text,
+                    Some(NodePseudoElement::Comment) => !comments,
+                    Some(NodePseudoElement::Attr(name)) => !attributes.contains(&name),
+                };
+                if !wanted || !selector.matches(document, element, Some(scope), &mut caches) {
+                    continue;
+                }
+                match pseudo {
+                    None => itself = true,
+                    Some(NodePseudoElement::Text) => text = true,
+                    Some(NodePseudoElement::Comment) => comments = true,
+                    Some(NodePseudoElement::Attr(name)) => attributes.push(name),
+                }
+            }
+
+            if itself {
+                nodes.push(SelectedNode::Node(element.as_node()));
+            }
+            if !attributes.is_empty() {
+                nodes.extend(
+                    element
+                        .attributes(document)
+                        .iter()
+                        .filter(|(name, _)| attributes.contains(name))
+                        .map(|(name, value)| SelectedNode::Attribute {
+                            element,
+                            name,
+                            value,
+                        }),
+                );
+            }
+            if text || comments {
+                nodes.extend(
+                    element
+                        .child_nodes(document)
+                        .iter()
+                        .filter(|node| match node {
+                            Node::Text(_) | Node::CDataSection(_) => text,
+                            Node::Comment(_) => comments,
+                            _ => false,
+                        })
+                        .map(|node| SelectedNode::Node(*node)),
+                );
+            }
+            caches.visit(document, edge);
+        }
+        nodes
+    }
+}
+
+impl SelectorInner {
+    fn new(selector: GenericSelector<Selectors>, local_names: bool) -> Self {
+        let mut hashes = vec![];
+        collect_ancestor_hashes(selector.iter(), false, &mut hashes);
+        hashes.resize(4, 0);
+        // The fourth hash is packed into the unused high bytes of the others.
+        let hashes = AncestorHashes {
+            packed_hashes: [
+                hashes[0] | (hashes[3] & 0xff) << 24,
+                hashes[1] | (hashes[3] & 0xff00) << 16,
+                hashes[2] | (hashes[3] & 0xff0000) << 8,
+            ],
+        };
+        SelectorInner {
+            selector,
+            hashes,
+            local_names,
+        }
+    }
+
+    fn pseudo_element(&self) -> Option<&NodePseudoElement> {
+        self.selector.pseudo_element()
+    }
+
+    /// Whether `element` matches, ignoring a trailing pseudo-element.
+    fn matches(
+        &self,
+        document: &Document,
+        element: Element,
+        scope: Option<Element>,
+        caches: &mut MatchingCaches,
+    ) -> bool {
+        let mode = match self.selector.has_pseudo_element() {
+            true => matching::MatchingMode::ForStatelessPseudoElement,
+            false => matching::MatchingMode::Normal,
+        };
         let mut context = matching::MatchingContext::new(
-            matching::MatchingMode::Normal,
-            None,
-            &mut cache,
+            mode,
+            caches.ancestors.as_deref(),
+            &mut caches.nth_index,
             QuirksMode::NoQuirks,
             NeedsSelectorFlags::No,
             IgnoreNthChildForInvalidation::No,
         );
-        self.0.iter().any(|s| {
-            matching::matches_selector(
-                &s.0,
-                0,
-                None,
-                &ElementRef { document, element },
-                &mut context,
-            )
-        })
+        context.scope_element = scope.map(|e| opaque(document, e));
+        matching::matches_selector(
+            &self.selector,
+            0,
+            Some(&self.hashes),
+            &SelectorElement {
+                document,
+                element,
+                local_names: self.local_names,
+            },
+            &mut context,
+        )
+    }
+}
+
+/// A node selected by [`Selector::select_nodes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectedNode<'d> {
+    /// An element, or a text, CDATA or comment node selected with a pseudo-element.
+    Node(Node),
+    /// An attribute selected with `::attr(name)`.
+    Attribute {
+        element: Element,
+        name: &'d QName,
+        value: &'d str,
+    },
+}
+
+impl<'d> SelectedNode<'d> {
+    pub fn as_node(&self) -> Option<Node> {
+        match self {
+            SelectedNode::Node(node) => Some(*node),
+            SelectedNode::Attribute { .. } => None,
+        }
+    }
+
+    /// The text of the node: the text content of an element, the text of other nodes and the
+    /// value of an attribute.
+    pub fn text(&self, document: &'d Document) -> Cow<'d, str> {
+        match self {
+            SelectedNode::Node(Node::Element(e)) => Cow::Owned(e.text_content(document)),
+            SelectedNode::Node(Node::Text(t)) => Cow::Borrowed(t.as_str(document)),
+            SelectedNode::Node(Node::CDataSection(t)) => Cow::Borrowed(t.as_str(document)),
+            SelectedNode::Node(Node::Comment(t)) => Cow::Borrowed(t.as_str(document)),
+            SelectedNode::Node(_) => Cow::Borrowed(""),
+            SelectedNode::Attribute { value, .. } => Cow::Borrowed(value),
+        }
     }
 }
@@ -23,7 +23,17 @@ impl NodeValue {
             NodeValue::Text(x)
             | NodeValue::CData(x)
             | NodeValue::Comment(x)
-            | NodeValue::DocumentType(x) => Some(&*x),
+            | NodeValue::DocumentType(x) => Some(x),
+            NodeValue::Element(_) => None,
+        }
+    }
+
+    pub fn as_string_mut(&mut self) -> Option<&mut String> {
+        match self {
+            NodeValue::Text(x)
+            | NodeValue::CData(x)
+            | NodeValue::Comment(x)
+            | NodeValue::DocumentType(x) => Some(x),
             NodeValue::Element(_) => None,
         }
     }
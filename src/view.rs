@@ -0,0 +1,389 @@
+use std::{fmt, ptr, str::FromStr, str::ParseBoolError, str::SplitWhitespace};
+
+use indexmap::IndexMap;
+use qname::QName;
+
+use crate::{AttributeError, Document, Element, ElementBuilder, Error, NewElement, Node, Selector};
+
+/// A read-only view of an element, so that getters and navigation need no document argument.
+///
+/// Navigation returns further views borrowing the same document. Convert back to a plain
+/// [`Element`] handle with [`ElementRef::element`] or `Element::from`.
+///
+/// ```
+/// let doc = r#"<keyboard><row n="1"><key code="q"/><key code="w"/></row></keyboard>"#
+///     .parse::<xmlem::Document>()
+///     .unwrap();
+/// let row = doc.root_ref().children().next().unwrap();
+/// let codes = row
+///     .children()
+///     .filter_map(|key| key.attribute("code"))
+///     .collect::<Vec<_>>();
+/// assert_eq!(codes, ["q", "w"]);
+/// assert_eq!(row.children().last().unwrap().parent(), Some(row));
+/// ```
+#[derive(Clone, Copy)]
+pub struct ElementRef<'d> {
+    document: &'d Document,
+    element: Element,
+}
+
+impl<'d> ElementRef<'d> {
+    pub fn new(document: &'d Document, element: Element) -> Self {
+        element.as_node().assert_belongs_to(document);
+        ElementRef { document, element }
+    }
+
+    fn wrap(self, element: Element) -> Self {
+        ElementRef {
+            document: self.document,
+            element,
+        }
+    }
+
+    pub fn element(self) -> Element {
+        self.element
+    }
+
+    pub fn document(self) -> &'d Document {
+        self.document
+    }
+
+    pub fn qname(self) -> &'d QName {
+        self.element.qname(self.document)
+    }
+
+    pub fn name(self) -> &'d str {
+        self.element.name(self.document)
+    }
+
+    pub fn prefix(self) -> Option<&'d str> {
+        self.element.prefix(self.document)
+    }
+
+    pub fn attributes(self) -> &'d IndexMap<QName, String> {
+        self.element.attributes(self.document)
+    }
+
+    pub fn attribute(self, name: &str) -> Option<&'d str> {
+        self.element.attribute(self.document, name)
+    }
+
+    pub fn attribute_as<T: FromStr>(self, name: &str) -> Result<Option<T>, AttributeError<T::Err>> {
+        self.element.attribute_as(self.document, name)
+    }
+
+    pub fn attribute_bool(
+        self,
+        name: &str,
+    ) -> Result<Option<bool>, AttributeError<ParseBoolError>> {
+        self.element.attribute_bool(self.document, name)
+    }
+
+    pub fn attribute_tokens(self, name: &str) -> SplitWhitespace<'d> {
+        self.element.attribute_tokens(self.document, name)
+    }
+
+    pub fn has_attribute_token(self, name: &str, token: &str) -> bool {
+        self.element.has_attribute_token(self.document, name, token)
+    }
+
+    pub fn text_content(self) -> String {
+        self.element.text_content(self.document)
+    }
+
+    pub fn own_text(self) -> String {
+        self.element.own_text(self.document)
+    }
+
+    pub fn display(self) -> String {
+        self.element.display(self.document)
+    }
+
+    pub fn to_document(self) -> Document {
+        self.element.to_document(self.document)
+    }
+
+    pub fn parent(self) -> Option<Self> {
+        self.element.parent(self.document).map(|e| self.wrap(e))
+    }
+
+    pub fn child_nodes(self) -> &'d [Node] {
+        self.element.child_nodes(self.document)
+    }
+
+    pub fn children(self) -> impl Iterator<Item = ElementRef<'d>> {
+        self.element
+            .child_elements(self.document)
+            .map(move |e| self.wrap(e))
+    }
+
+    pub fn first_child(self) -> Option<Node> {
+        self.element.first_child(self.document)
+    }
+
+    pub fn last_child(self) -> Option<Node> {
+        self.element.last_child(self.document)
+    }
+
+    pub fn child_count(self) -> usize {
+        self.element.child_count(self.document)
+    }
+
+    pub fn descendants(self) -> impl Iterator<Item = ElementRef<'d>> {
+        self.element
+            .descendants(self.document)
+            .map(move |e| self.wrap(e))
+    }
+
+    pub fn ancestors(self) -> impl Iterator<Item = ElementRef<'d>> {
+        self.element
+            .ancestors(self.document)
+            .map(move |e| self.wrap(e))
+    }
+
+    pub fn following_siblings(self) -> impl Iterator<Item = ElementRef<'d>> {
+        self.element
+            .following_siblings(self.document)
+            .map(move |e| self.wrap(e))
+    }
+
+    pub fn preceding_siblings(self) -> impl Iterator<Item = ElementRef<'d>> {
+        self.element
+            .preceding_siblings(self.document)
+            .map(move |e| self.wrap(e))
+    }
+
+    pub fn next_sibling_element(self) -> Option<Self> {
+        self.following_siblings().next()
+    }
+
+    pub fn prev_sibling_element(self) -> Option<Self> {
+        self.preceding_siblings().next()
+    }
+
+    pub fn query_selector(self, selector: &Selector) -> Option<Self> {
+        self.element
+            .query_selector(self.document, selector)
+            .map(|e| self.wrap(e))
+    }
+
+    pub fn query_selector_all(self, selector: &Selector) -> Vec<Self> {
+        self.element
+            .query_selector_all(self.document, selector)
+            .into_iter()
+            .map(|e| self.wrap(e))
+            .collect()
+    }
+
+    pub fn query_selector_iter(self, selector: &'d Selector) -> impl Iterator<Item = Self> {
+        self.element
+            .query_selector_iter(self.document, selector)
+            .map(move |e| self.wrap(e))
+    }
+
+    pub fn matches(self, selector: &Selector) -> bool {
+        self.element.matches(self.document, selector)
+    }
+
+    pub fn closest(self, selector: &Selector) -> Option<Self> {
+        self.element
+            .closest(self.document, selector)
+            .map(|e| self.wrap(e))
+    }
+
+    pub fn path(self) -> Option<String> {
+        self.element.path(self.document)
+    }
+
+    pub fn path_with_keys(self, keys: &[&str]) -> Option<String> {
+        self.element.path_with_keys(self.document, keys)
+    }
+}
+
+/// Views are equal if they refer to the same element of the same document.
+impl PartialEq for ElementRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.element == other.element && ptr::eq(self.document, other.document)
+    }
+}
+
+impl Eq for ElementRef<'_> {}
+
+impl fmt::Debug for ElementRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ElementRef")
+            .field(&self.element.0)
+            .field(&self.name())
+            .finish()
+    }
+}
+
+impl From<ElementRef<'_>> for Element {
+    fn from(element: ElementRef<'_>) -> Self {
+        element.element
+    }
+}
+
+/// A view of an element that borrows its document mutably, for chained edits.
+///
+/// Edits panic where the corresponding [`Element`] method panics; use the `try_` methods on the
+/// handle from [`ElementMut::element`] for fallible alternatives. Read through
+/// [`ElementMut::as_ref`].
+///
+/// ```
+/// let mut doc = "<root/>".parse::<xmlem::Document>().unwrap();
+/// let mut root = doc.root_mut();
+/// root.set_attribute("version", 2)
+///     .append_text("a")
+///     .append_new_element(("b", [("x", "1")]))
+///     .append_comment("c");
+/// assert_eq!(root.as_ref().child_count(), 2);
+/// assert_eq!(doc.to_string(), r#"<root version="2">a<b x="1"><!--c--></b></root>"#);
+/// ```
+pub struct ElementMut<'d> {
+    document: &'d mut Document,
+    element: Element,
+}
+
+impl<'d> ElementMut<'d> {
+    pub fn new(document: &'d mut Document, element: Element) -> Self {
+        element.as_node().assert_belongs_to(document);
+        ElementMut { document, element }
+    }
+
+    pub fn element(&self) -> Element {
+        self.element
+    }
+
+    pub fn document(&mut self) -> &mut Document {
+        self.document
+    }
+
+    pub fn as_ref(&self) -> ElementRef<'_> {
+        ElementRef {
+            document: self.document,
+            element: self.element,
+        }
+    }
+
+    pub fn into_ref(self) -> ElementRef<'d> {
+        ElementRef {
+            document: self.document,
+            element: self.element,
+        }
+    }
+
+    pub fn set_name(&mut self, name: &str) -> &mut Self {
+        if let Err(e) = self.element.try_set_name(self.document, name) {
+            panic!("{e}");
+        }
+        self
+    }
+
+    pub fn set_attribute(&mut self, name: &str, value: impl fmt::Display) -> &mut Self {
+        self.element.set_attribute_value(self.document, name, value);
+        self
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) -> &mut Self {
+        self.element.remove_attribute(self.document, name);
+        self
+    }
+
+    pub fn add_attribute_token(&mut self, name: &str, token: &str) -> &mut Self {
+        self.element.add_attribute_token(self.document, name, token);
+        self
+    }
+
+    pub fn remove_attribute_token(&mut self, name: &str, token: &str) -> &mut Self {
+        self.element
+            .remove_attribute_token(self.document, name, token);
+        self
+    }
+
+    /// Move an existing node to the end of this element's children.
+    pub fn append(&mut self, node: impl Into<Node>) -> &mut Self {
+        if let Err(e) = self.element.append(self.document, node) {
+            panic!("{e}");
+        }
+        self
+    }
+
+    /// Move an existing node to the start of this element's children.
+    pub fn prepend(&mut self, node: impl Into<Node>) -> &mut Self {
+        if let Err(e) = self.element.prepend(self.document, node) {
+            panic!("{e}");
+        }
+        self
+    }
+
+    /// Append a new element and continue with a view of it.
+    pub fn append_new_element(&mut self, element: impl Into<NewElement>) -> ElementMut<'_> {
+        let element = self.element.append_new_element(self.document, element);
+        ElementMut {
+            document: self.document,
+            element,
+        }
+    }
+
+    /// Insert a new element as the next sibling of this one and continue with a view of it.
+    /// Fails if this element is the root or detached.
+    pub fn try_append_new_element_after(
+        &mut self,
+        element: impl Into<NewElement>,
+    ) -> Result<ElementMut<'_>, Error> {
+        let element = self
+            .element
+            .try_append_new_element_after(self.document, element)?;
+        Ok(ElementMut {
+            document: self.document,
+            element,
+        })
+    }
+
+    pub fn append_text(&mut self, text: &str) -> &mut Self {
+        self.element.append_text(self.document, text);
+        self
+    }
+
+    pub fn append_cdata(&mut self, text: &str) -> &mut Self {
+        self.element.append_cdata(self.document, text);
+        self
+    }
+
+    pub fn append_comment(&mut self, text: &str) -> &mut Self {
+        self.element.append_comment(self.document, text);
+        self
+    }
+
+    pub fn append_builder(&mut self, builder: ElementBuilder) -> &mut Self {
+        builder.append_to(self.document, self.element);
+        self
+    }
+
+    pub fn set_text(&mut self, text: &str) -> &mut Self {
+        self.element.set_text(self.document, text);
+        self
+    }
+
+    pub fn set_text_content(&mut self, text: &str) -> &mut Self {
+        self.element.set_text_content(self.document, text);
+        self
+    }
+}
+
+impl fmt::Debug for ElementMut<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ElementMut")
+            .field(&self.element.0)
+            .field(&self.as_ref().name())
+            .finish()
+    }
+}
+
+impl From<ElementMut<'_>> for Element {
+    fn from(element: ElementMut<'_>) -> Self {
+        element.element
+    }
+}
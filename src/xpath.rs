@@ -0,0 +1,1653 @@
+use std::{collections::HashMap, fmt, iter};
+
+use qname::QName;
+
+use crate::{Document, Element, Node};
+
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// A compiled XPath 1.0 expression.
+///
+/// Names with a prefix are matched by namespace URI, using the bindings given to
+/// [`XPath::with_namespaces`]; the `xml` prefix is always bound. As in XPath 1.0, names without
+/// a prefix only match elements in no namespace, even where the document declares a default
+/// namespace.
+///
+/// The namespace axis is always empty, `id()` finds elements by their `id` or `xml:id`
+/// attribute, and CDATA sections are text nodes of their own rather than being merged with
+/// adjacent text.
+///
+/// ```
+/// use xmlem::{Document, XPath, XPathNode, XPathValue};
+///
+/// let doc = r#"<resources xmlns:tools="urn:tools"><string name="app">Mlem</string><string name="hi" tools:ignore="x">Hi <b>there</b></string></resources>"#
+///     .parse::<Document>()
+///     .unwrap();
+///
+/// let names = XPath::new("//string[b]/@name").unwrap();
+/// let nodes = names.select(&doc, XPathNode::Document).unwrap();
+/// assert_eq!(nodes.len(), 1);
+/// assert_eq!(nodes[0].string_value(&doc), "hi");
+///
+/// let count = XPath::new("count(string) * 10").unwrap();
+/// assert_eq!(count.evaluate(&doc, doc.root()).unwrap(), XPathValue::Number(20.0));
+///
+/// let ignored = XPath::with_namespaces("string(//*[@t:ignore]/b)", [("t", "urn:tools")]).unwrap();
+/// assert_eq!(
+///     ignored.evaluate(&doc, doc.root()).unwrap(),
+///     XPathValue::String("there".into())
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct XPath {
+    source: String,
+    expr: Expr,
+}
+
+/// A node that an XPath expression can select.
+///
+/// Attributes are not nodes of their own in a [`Document`], so they are identified by their
+/// element and name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XPathNode {
+    /// The document itself, the parent of the root element and of the nodes around it.
+    Document,
+    Node(Node),
+    Attribute(Element, QName),
+}
+
+/// The result of evaluating an XPath expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum XPathValue {
+    /// A node-set, in document order and without duplicates.
+    Nodes(Vec<XPathNode>),
+    String(String),
+    Number(f64),
+    Boolean(bool),
+}
+
+/// Error returned when an XPath expression cannot be compiled or evaluated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum XPathError {
+    /// The expression is not valid XPath 1.0. The position is a byte offset into it.
+    Syntax { position: usize, message: String },
+    /// A prefix used in the expression has no namespace binding.
+    UnboundPrefix(String),
+    /// A variable used in the expression was not supplied.
+    UnknownVariable(String),
+    /// A value that is not a node-set was used where one is required, such as in a path or a
+    /// union.
+    NotANodeSet,
+}
+
+impl fmt::Display for XPathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            XPathError::Syntax { position, message } => {
+                write!(f, "Invalid XPath at position {position}: {message}")
+            }
+            XPathError::UnboundPrefix(prefix) => write!(f, "Unbound namespace prefix: {prefix}"),
+            XPathError::UnknownVariable(name) => write!(f, "Unknown variable: ${name}"),
+            XPathError::NotANodeSet => write!(f, "Expression does not evaluate to a node-set"),
+        }
+    }
+}
+
+impl std::error::Error for XPathError {}
+
+impl XPath {
+    pub fn new(expression: &str) -> Result<XPath, XPathError> {
+        Self::with_namespaces(expression, [])
+    }
+
+    /// Compile an expression whose prefixes are bound to the given namespace URIs.
+    pub fn with_namespaces<'a>(
+        expression: &str,
+        namespaces: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<XPath, XPathError> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            index: 0,
+            end: expression.len(),
+            namespaces: namespaces
+                .into_iter()
+                .map(|(prefix, uri)| (prefix.to_string(), uri.to_string()))
+                .collect(),
+        };
+        let expr = parser.expr()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unexpected token"));
+        }
+        Ok(XPath {
+            source: expression.to_string(),
+            expr,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluate with `context` as the context node. Use [`XPathNode::Document`] to evaluate
+    /// against the document as a whole.
+    pub fn evaluate(
+        &self,
+        document: &Document,
+        context: impl Into<XPathNode>,
+    ) -> Result<XPathValue, XPathError> {
+        self.evaluate_with(document, context, &HashMap::new())
+    }
+
+    /// Evaluate with values for the variables referenced as `$name` in the expression.
+    pub fn evaluate_with(
+        &self,
+        document: &Document,
+        context: impl Into<XPathNode>,
+        variables: &HashMap<String, XPathValue>,
+    ) -> Result<XPathValue, XPathError> {
+        let node = context.into();
+        match &node {
+            XPathNode::Document => {}
+            XPathNode::Node(n) => n.assert_belongs_to(document),
+            XPathNode::Attribute(e, _) => e.as_node().assert_belongs_to(document),
+        }
+        let evaluator = Evaluator {
+            document,
+            variables,
+        };
+        let focus = Focus {
+            node,
+            position: 1,
+            size: 1,
+        };
+        evaluator.eval(&self.expr, &focus)
+    }
+
+    /// Evaluate an expression that results in a node-set, returning its nodes in document
+    /// order.
+    pub fn select(
+        &self,
+        document: &Document,
+        context: impl Into<XPathNode>,
+    ) -> Result<Vec<XPathNode>, XPathError> {
+        match self.evaluate(document, context)? {
+            XPathValue::Nodes(nodes) => Ok(nodes),
+            _ => Err(XPathError::NotANodeSet),
+        }
+    }
+}
+
+impl fmt::Display for XPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl XPathNode {
+    pub fn as_node(&self) -> Option<Node> {
+        match self {
+            XPathNode::Node(node) => Some(*node),
+            _ => None,
+        }
+    }
+
+    pub fn as_element(&self) -> Option<Element> {
+        self.as_node().and_then(Node::as_element)
+    }
+
+    /// The text of this node as XPath defines it: the concatenated text of all descendants for
+    /// the document and elements, and the value of an attribute.
+    pub fn string_value(&self, document: &Document) -> String {
+        match self {
+            XPathNode::Document => document.root().text_content(document),
+            XPathNode::Node(Node::Element(e)) => e.text_content(document),
+            XPathNode::Node(Node::Text(t)) => t.as_str(document).to_string(),
+            XPathNode::Node(Node::CDataSection(t)) => t.as_str(document).to_string(),
+            XPathNode::Node(Node::Comment(t)) => t.as_str(document).to_string(),
+            XPathNode::Node(_) => String::new(),
+            XPathNode::Attribute(e, name) => e
+                .attributes(document)
+                .get(name)
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl From<Node> for XPathNode {
+    fn from(node: Node) -> Self {
+        XPathNode::Node(node)
+    }
+}
+
+impl From<Element> for XPathNode {
+    fn from(element: Element) -> Self {
+        XPathNode::Node(element.as_node())
+    }
+}
+
+impl XPathValue {
+    /// Convert to a boolean like the `boolean()` function.
+    pub fn to_boolean(&self) -> bool {
+        match self {
+            XPathValue::Nodes(nodes) => !nodes.is_empty(),
+            XPathValue::String(s) => !s.is_empty(),
+            XPathValue::Number(n) => *n != 0.0 && !n.is_nan(),
+            XPathValue::Boolean(b) => *b,
+        }
+    }
+
+    /// Convert to a number like the `number()` function.
+    pub fn to_number(&self, document: &Document) -> f64 {
+        match self {
+            XPathValue::Nodes(_) => parse_number(&self.to_string_value(document)),
+            XPathValue::String(s) => parse_number(s),
+            XPathValue::Number(n) => *n,
+            XPathValue::Boolean(b) => f64::from(u8::from(*b)),
+        }
+    }
+
+    /// Convert to a string like the `string()` function. A node-set is converted to the string
+    /// value of its first node.
+    pub fn to_string_value(&self, document: &Document) -> String {
+        match self {
+            XPathValue::Nodes(nodes) => nodes
+                .first()
+                .map(|n| n.string_value(document))
+                .unwrap_or_default(),
+            XPathValue::String(s) => s.clone(),
+            XPathValue::Number(n) => format_number(*n),
+            XPathValue::Boolean(b) => b.to_string(),
+        }
+    }
+}
+
+/// Parses a number the way XPath converts strings: an optional minus sign and digits with an
+/// optional decimal point, surrounded by whitespace. Anything else is NaN.
+fn parse_number(s: &str) -> f64 {
+    let s = s.trim_matches(is_xml_space);
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+    let valid = !(int.is_empty() && frac.is_empty())
+        && int.bytes().all(|b| b.is_ascii_digit())
+        && frac.bytes().all(|b| b.is_ascii_digit());
+    match valid {
+        true => s.parse().unwrap_or(f64::NAN),
+        false => f64::NAN,
+    }
+}
+
+fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        match n > 0.0 {
+            true => "Infinity".to_string(),
+            false => "-Infinity".to_string(),
+        }
+    } else if n == 0.0 {
+        "0".to_string()
+    } else {
+        n.to_string()
+    }
+}
+
+fn is_xml_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n')
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Dot,
+    DotDot,
+    At,
+    Comma,
+    ColonColon,
+    Slash,
+    DoubleSlash,
+    Pipe,
+    Plus,
+    Minus,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `*` as a name test.
+    Star,
+    /// `*` as an operator.
+    Multiply,
+    And,
+    Or,
+    Mod,
+    Div,
+    Name(Option<String>, String),
+    /// `prefix:*`
+    PrefixStar(String),
+    Literal(String),
+    Number(f64),
+    Variable(String),
+}
+
+impl Token {
+    /// Whether an operand, rather than an operator, follows this token. This decides whether
+    /// `*` is a name test or multiplication and whether `div` is a name or an operator.
+    fn precedes_operand(&self) -> bool {
+        !matches!(
+            self,
+            Token::RParen
+                | Token::RBracket
+                | Token::Dot
+                | Token::DotDot
+                | Token::Star
+                | Token::Name(..)
+                | Token::PrefixStar(_)
+                | Token::Literal(_)
+                | Token::Number(_)
+                | Token::Variable(_)
+        )
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '.' | '-' | '_' | '\u{B7}')
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, XPathError> {
+    let syntax = |position, message: &str| XPathError::Syntax {
+        position,
+        message: message.to_string(),
+    };
+    let name_end = |from: usize| {
+        input[from..]
+            .char_indices()
+            .find(|(_, c)| !is_name_char(*c))
+            .map_or(input.len(), |(i, _)| from + i)
+    };
+
+    let mut tokens: Vec<(usize, Token)> = vec![];
+    let mut pos = 0;
+    while let Some(c) = input[pos..].chars().next() {
+        let start = pos;
+        let rest = &input[pos..];
+        let operand = match tokens.last() {
+            Some((_, t)) => t.precedes_operand(),
+            None => true,
+        };
+
+        if is_xml_space(c) {
+            pos += 1;
+            continue;
+        }
+
+        let (token, len) = match c {
+            '(' => (Token::LParen, 1),
+            ')' => (Token::RParen, 1),
+            '[' => (Token::LBracket, 1),
+            ']' => (Token::RBracket, 1),
+            '@' => (Token::At, 1),
+            ',' => (Token::Comma, 1),
+            '|' => (Token::Pipe, 1),
+            '+' => (Token::Plus, 1),
+            '-' => (Token::Minus, 1),
+            '=' => (Token::Eq, 1),
+            '*' if operand => (Token::Star, 1),
+            '*' => (Token::Multiply, 1),
+            '/' if rest.starts_with("//") => (Token::DoubleSlash, 2),
+            '/' => (Token::Slash, 1),
+            ':' if rest.starts_with("::") => (Token::ColonColon, 2),
+            '!' if rest.starts_with("!=") => (Token::Ne, 2),
+            '<' if rest.starts_with("<=") => (Token::Le, 2),
+            '<' => (Token::Lt, 1),
+            '>' if rest.starts_with(">=") => (Token::Ge, 2),
+            '>' => (Token::Gt, 1),
+            '.' if rest.starts_with("..") => (Token::DotDot, 2),
+            '.' if !rest[1..].starts_with(|c: char| c.is_ascii_digit()) => (Token::Dot, 1),
+            '"' | '\'' => match rest[1..].find(c) {
+                Some(end) => (Token::Literal(rest[1..end + 1].to_string()), end + 2),
+                None => return Err(syntax(start, "unterminated string literal")),
+            },
+            '.' | '0'..='9' => {
+                let int = rest.bytes().take_while(u8::is_ascii_digit).count();
+                let len = match rest[int..].strip_prefix('.') {
+                    Some(frac) => int + 1 + frac.bytes().take_while(u8::is_ascii_digit).count(),
+                    None => int,
+                };
+                (Token::Number(rest[..len].parse().unwrap()), len)
+            }
+            '$' => {
+                let end = match rest[1..].starts_with(is_name_start) {
+                    true => name_end(start + 1),
+                    false => return Err(syntax(start, "expected a variable name")),
+                };
+                let end = match input[end..].strip_prefix(':') {
+                    Some(local) if local.starts_with(is_name_start) => name_end(end + 1),
+                    _ => end,
+                };
+                (
+                    Token::Variable(input[start + 1..end].to_string()),
+                    end - start,
+                )
+            }
+            c if is_name_start(c) => {
+                let end = name_end(start);
+                let name = &input[start..end];
+                if !operand {
+                    let token = match name {
+                        "and" => Token::And,
+                        "or" => Token::Or,
+                        "mod" => Token::Mod,
+                        "div" => Token::Div,
+                        _ => return Err(syntax(start, "expected an operator")),
+                    };
+                    (token, end - start)
+                } else {
+                    match input[end..].strip_prefix(':') {
+                        Some(local) if local.starts_with('*') => {
+                            (Token::PrefixStar(name.to_string()), end + 2 - start)
+                        }
+                        Some(local) if local.starts_with(is_name_start) => {
+                            let local_end = name_end(end + 1);
+                            let token = Token::Name(
+                                Some(name.to_string()),
+                                input[end + 1..local_end].to_string(),
+                            );
+                            (token, local_end - start)
+                        }
+                        _ => (Token::Name(None, name.to_string()), end - start),
+                    }
+                }
+            }
+            _ => return Err(syntax(start, &format!("unexpected character {c:?}"))),
+        };
+
+        tokens.push((start, token));
+        pos += len;
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Compare(Comparison, Box<Expr>, Box<Expr>),
+    Arithmetic(Arithmetic, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Union(Box<Expr>, Box<Expr>),
+    Path(Start, Vec<Step>),
+    Filter(Box<Expr>, Vec<Expr>),
+    Literal(String),
+    Number(f64),
+    Variable(String),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+#[derive(Debug, Clone)]
+enum Start {
+    Context,
+    Root,
+    Expr(Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    axis: Axis,
+    test: NodeTest,
+    predicates: Vec<Expr>,
+}
+
+impl Step {
+    fn descendant_or_self() -> Self {
+        Step {
+            axis: Axis::DescendantOrSelf,
+            test: NodeTest::Node,
+            predicates: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis {
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Following,
+    FollowingSibling,
+    Namespace,
+    Parent,
+    Preceding,
+    PrecedingSibling,
+    Itself,
+}
+
+impl Axis {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "ancestor" => Axis::Ancestor,
+            "ancestor-or-self" => Axis::AncestorOrSelf,
+            "attribute" => Axis::Attribute,
+            "child" => Axis::Child,
+            "descendant" => Axis::Descendant,
+            "descendant-or-self" => Axis::DescendantOrSelf,
+            "following" => Axis::Following,
+            "following-sibling" => Axis::FollowingSibling,
+            "namespace" => Axis::Namespace,
+            "parent" => Axis::Parent,
+            "preceding" => Axis::Preceding,
+            "preceding-sibling" => Axis::PrecedingSibling,
+            "self" => Axis::Itself,
+            _ => return None,
+        })
+    }
+
+    fn is_reverse(self) -> bool {
+        matches!(
+            self,
+            Axis::Ancestor | Axis::AncestorOrSelf | Axis::Preceding | Axis::PrecedingSibling
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+enum NodeTest {
+    /// `*`
+    Any,
+    /// `prefix:*`, holding the namespace URI.
+    Namespace(String),
+    Name {
+        namespace: Option<String>,
+        local: String,
+    },
+    Node,
+    Text,
+    Comment,
+    ProcessingInstruction,
+}
+
+fn is_node_type(name: &str) -> bool {
+    matches!(name, "node" | "text" | "comment" | "processing-instruction")
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Function {
+    Last,
+    Position,
+    Count,
+    Id,
+    LocalName,
+    NamespaceUri,
+    Name,
+    String,
+    Concat,
+    StartsWith,
+    Contains,
+    SubstringBefore,
+    SubstringAfter,
+    Substring,
+    StringLength,
+    NormalizeSpace,
+    Translate,
+    Boolean,
+    Not,
+    True,
+    False,
+    Lang,
+    Number,
+    Sum,
+    Floor,
+    Ceiling,
+    Round,
+}
+
+impl Function {
+    /// The function with this name and the range of arguments it accepts.
+    fn from_name(name: &str) -> Option<(Self, usize, usize)> {
+        Some(match name {
+            "last" => (Function::Last, 0, 0),
+            "position" => (Function::Position, 0, 0),
+            "count" => (Function::Count, 1, 1),
+            "id" => (Function::Id, 1, 1),
+            "local-name" => (Function::LocalName, 0, 1),
+            "namespace-uri" => (Function::NamespaceUri, 0, 1),
+            "name" => (Function::Name, 0, 1),
+            "string" => (Function::String, 0, 1),
+            "concat" => (Function::Concat, 2, usize::MAX),
+            "starts-with" => (Function::StartsWith, 2, 2),
+            "contains" => (Function::Contains, 2, 2),
+            "substring-before" => (Function::SubstringBefore, 2, 2),
+            "substring-after" => (Function::SubstringAfter, 2, 2),
+            "substring" => (Function::Substring, 2, 3),
+            "string-length" => (Function::StringLength, 0, 1),
+            "normalize-space" => (Function::NormalizeSpace, 0, 1),
+            "translate" => (Function::Translate, 3, 3),
+            "boolean" => (Function::Boolean, 1, 1),
+            "not" => (Function::Not, 1, 1),
+            "true" => (Function::True, 0, 0),
+            "false" => (Function::False, 0, 0),
+            "lang" => (Function::Lang, 1, 1),
+            "number" => (Function::Number, 0, 1),
+            "sum" => (Function::Sum, 1, 1),
+            "floor" => (Function::Floor, 1, 1),
+            "ceiling" => (Function::Ceiling, 1, 1),
+            "round" => (Function::Round, 1, 1),
+            _ => return None,
+        })
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    /// The length of the expression, reported as the position of errors at its end.
+    end: usize,
+    namespaces: HashMap<String, String>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.index + offset).map(|(_, t)| t)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).map(|(_, t)| t.clone());
+        self.index += 1;
+        token
+    }
+
+    /// Consumes the next token if it is `token`.
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.index += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, token: Token, what: &str) -> Result<(), XPathError> {
+        match self.eat(&token) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected {what}"))),
+        }
+    }
+
+    fn error(&self, message: &str) -> XPathError {
+        XPathError::Syntax {
+            position: self.tokens.get(self.index).map_or(self.end, |(p, _)| *p),
+            message: message.to_string(),
+        }
+    }
+
+    fn namespace(&self, prefix: &str) -> Result<String, XPathError> {
+        if prefix == "xml" {
+            return Ok(XML_NAMESPACE.to_string());
+        }
+        match self.namespaces.get(prefix) {
+            Some(uri) => Ok(uri.clone()),
+            None => Err(XPathError::UnboundPrefix(prefix.to_string())),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.and_expr()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.equality_expr()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.equality_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn equality_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.relational_expr()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Eq) => Comparison::Eq,
+                Some(Token::Ne) => Comparison::Ne,
+                _ => return Ok(expr),
+            };
+            self.index += 1;
+            expr = Expr::Compare(op, Box::new(expr), Box::new(self.relational_expr()?));
+        }
+    }
+
+    fn relational_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.additive_expr()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Lt) => Comparison::Lt,
+                Some(Token::Le) => Comparison::Le,
+                Some(Token::Gt) => Comparison::Gt,
+                Some(Token::Ge) => Comparison::Ge,
+                _ => return Ok(expr),
+            };
+            self.index += 1;
+            expr = Expr::Compare(op, Box::new(expr), Box::new(self.additive_expr()?));
+        }
+    }
+
+    fn additive_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.multiplicative_expr()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => Arithmetic::Add,
+                Some(Token::Minus) => Arithmetic::Subtract,
+                _ => return Ok(expr),
+            };
+            self.index += 1;
+            expr = Expr::Arithmetic(op, Box::new(expr), Box::new(self.multiplicative_expr()?));
+        }
+    }
+
+    fn multiplicative_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.unary_expr()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Multiply) => Arithmetic::Multiply,
+                Some(Token::Div) => Arithmetic::Divide,
+                Some(Token::Mod) => Arithmetic::Modulo,
+                _ => return Ok(expr),
+            };
+            self.index += 1;
+            expr = Expr::Arithmetic(op, Box::new(expr), Box::new(self.unary_expr()?));
+        }
+    }
+
+    fn unary_expr(&mut self) -> Result<Expr, XPathError> {
+        match self.eat(&Token::Minus) {
+            true => Ok(Expr::Negate(Box::new(self.unary_expr()?))),
+            false => self.union_expr(),
+        }
+    }
+
+    fn union_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.path_expr()?;
+        while self.eat(&Token::Pipe) {
+            expr = Expr::Union(Box::new(expr), Box::new(self.path_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn path_expr(&mut self) -> Result<Expr, XPathError> {
+        let starts_location_path = match self.peek() {
+            Some(Token::Name(prefix, local)) => {
+                self.peek_at(1) != Some(&Token::LParen) || (prefix.is_none() && is_node_type(local))
+            }
+            Some(token) => matches!(
+                token,
+                Token::Slash
+                    | Token::DoubleSlash
+                    | Token::Dot
+                    | Token::DotDot
+                    | Token::At
+                    | Token::Star
+                    | Token::PrefixStar(_)
+            ),
+            None => false,
+        };
+        if starts_location_path {
+            return self.location_path();
+        }
+
+        let primary = self.primary_expr()?;
+        let predicates = self.predicates()?;
+        let filter = match predicates.is_empty() {
+            true => primary,
+            false => Expr::Filter(Box::new(primary), predicates),
+        };
+
+        let mut steps = vec![];
+        match self.peek() {
+            Some(Token::Slash) => self.index += 1,
+            Some(Token::DoubleSlash) => {
+                self.index += 1;
+                steps.push(Step::descendant_or_self());
+            }
+            _ => return Ok(filter),
+        }
+        self.relative_path(&mut steps)?;
+        Ok(Expr::Path(Start::Expr(Box::new(filter)), steps))
+    }
+
+    fn location_path(&mut self) -> Result<Expr, XPathError> {
+        let mut steps = vec![];
+        let start = match self.peek() {
+            Some(Token::Slash) => {
+                self.index += 1;
+                let starts_step = matches!(
+                    self.peek(),
+                    Some(
+                        Token::Dot
+                            | Token::DotDot
+                            | Token::At
+                            | Token::Star
+                            | Token::PrefixStar(_)
+                            | Token::Name(..)
+                    )
+                );
+                if !starts_step {
+                    return Ok(Expr::Path(Start::Root, steps));
+                }
+                Start::Root
+            }
+            Some(Token::DoubleSlash) => {
+                self.index += 1;
+                steps.push(Step::descendant_or_self());
+                Start::Root
+            }
+            _ => Start::Context,
+        };
+        self.relative_path(&mut steps)?;
+        Ok(Expr::Path(start, steps))
+    }
+
+    fn relative_path(&mut self, steps: &mut Vec<Step>) -> Result<(), XPathError> {
+        loop {
+            let step = self.step()?;
+            // `//name` is the same as `descendant::name` unless predicates count positions
+            // among each parent's children, and is much cheaper to evaluate.
+            let after_double_slash = matches!(
+                steps.last(),
+                Some(Step { axis: Axis::DescendantOrSelf, test: NodeTest::Node, predicates })
+                    if predicates.is_empty()
+            );
+            if after_double_slash && step.axis == Axis::Child && step.predicates.is_empty() {
+                steps.pop();
+                steps.push(Step {
+                    axis: Axis::Descendant,
+                    ..step
+                });
+            } else {
+                steps.push(step);
+            }
+
+            match self.peek() {
+                Some(Token::Slash) => self.index += 1,
+                Some(Token::DoubleSlash) => {
+                    self.index += 1;
+                    steps.push(Step::descendant_or_self());
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn step(&mut self) -> Result<Step, XPathError> {
+        if self.eat(&Token::Dot) {
+            return Ok(Step {
+                axis: Axis::Itself,
+                test: NodeTest::Node,
+                predicates: vec![],
+            });
+        }
+        if self.eat(&Token::DotDot) {
+            return Ok(Step {
+                axis: Axis::Parent,
+                test: NodeTest::Node,
+                predicates: vec![],
+            });
+        }
+
+        let axis = match (self.peek(), self.peek_at(1)) {
+            (Some(Token::At), _) => {
+                self.index += 1;
+                Axis::Attribute
+            }
+            (Some(Token::Name(None, name)), Some(Token::ColonColon)) => {
+                let axis = Axis::from_name(name);
+                let axis = axis.ok_or_else(|| self.error(&format!("unknown axis {name}")))?;
+                self.index += 2;
+                axis
+            }
+            _ => Axis::Child,
+        };
+
+        let test = match self.next() {
+            Some(Token::Star) => NodeTest::Any,
+            Some(Token::PrefixStar(prefix)) => NodeTest::Namespace(self.namespace(&prefix)?),
+            Some(Token::Name(None, name)) if self.peek() == Some(&Token::LParen) => {
+                self.index += 1;
+                let test = match name.as_str() {
+                    "node" => NodeTest::Node,
+                    "text" => NodeTest::Text,
+                    "comment" => NodeTest::Comment,
+                    "processing-instruction" => {
+                        if let Some(Token::Literal(_)) = self.peek() {
+                            self.index += 1;
+                        }
+                        NodeTest::ProcessingInstruction
+                    }
+                    _ => {
+                        self.index -= 2;
+                        return Err(self.error(&format!("unknown node type {name}")));
+                    }
+                };
+                self.expect(Token::RParen, "')'")?;
+                test
+            }
+            Some(Token::Name(prefix, local)) => NodeTest::Name {
+                namespace: prefix.map(|p| self.namespace(&p)).transpose()?,
+                local,
+            },
+            _ => {
+                self.index -= 1;
+                return Err(self.error("expected a node test"));
+            }
+        };
+
+        Ok(Step {
+            axis,
+            test,
+            predicates: self.predicates()?,
+        })
+    }
+
+    fn predicates(&mut self) -> Result<Vec<Expr>, XPathError> {
+        let mut predicates = vec![];
+        while self.eat(&Token::LBracket) {
+            predicates.push(self.expr()?);
+            self.expect(Token::RBracket, "']'")?;
+        }
+        Ok(predicates)
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr, XPathError> {
+        match self.next() {
+            Some(Token::Variable(name)) => Ok(Expr::Variable(name)),
+            Some(Token::Literal(s)) => Ok(Expr::Literal(s)),
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::LParen) => {
+                let expr = self.expr()?;
+                self.expect(Token::RParen, "')'")?;
+                Ok(expr)
+            }
+            Some(Token::Name(prefix, local)) => {
+                let name = match prefix {
+                    Some(prefix) => format!("{prefix}:{local}"),
+                    None => local,
+                };
+                self.index -= 1;
+                let (function, min, max) = Function::from_name(&name)
+                    .ok_or_else(|| self.error(&format!("unknown function {name}()")))?;
+                self.index += 2;
+
+                let mut args = vec![];
+                if !self.eat(&Token::RParen) {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(&Token::RParen) {
+                            break;
+                        }
+                        self.expect(Token::Comma, "',' or ')'")?;
+                    }
+                }
+                if args.len() < min || args.len() > max {
+                    self.index -= 1;
+                    return Err(self.error(&format!(
+                        "wrong number of arguments for {name}(): {}",
+                        args.len()
+                    )));
+                }
+                Ok(Expr::Call(function, args))
+            }
+            _ => {
+                self.index = self.index.saturating_sub(1);
+                Err(self.error("expected an expression"))
+            }
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    document: &'a Document,
+    variables: &'a HashMap<String, XPathValue>,
+}
+
+/// The context node of an expression with its position and the size of the context.
+struct Focus {
+    node: XPathNode,
+    position: usize,
+    size: usize,
+}
+
+impl Evaluator<'_> {
+    fn eval(&self, expr: &Expr, focus: &Focus) -> Result<XPathValue, XPathError> {
+        Ok(match expr {
+            Expr::Or(a, b) => XPathValue::Boolean(
+                self.eval(a, focus)?.to_boolean() || self.eval(b, focus)?.to_boolean(),
+            ),
+            Expr::And(a, b) => XPathValue::Boolean(
+                self.eval(a, focus)?.to_boolean() && self.eval(b, focus)?.to_boolean(),
+            ),
+            Expr::Compare(op, a, b) => {
+                XPathValue::Boolean(self.compare(*op, self.eval(a, focus)?, self.eval(b, focus)?))
+            }
+            Expr::Arithmetic(op, a, b) => {
+                let a = self.eval(a, focus)?.to_number(self.document);
+                let b = self.eval(b, focus)?.to_number(self.document);
+                XPathValue::Number(match op {
+                    Arithmetic::Add => a + b,
+                    Arithmetic::Subtract => a - b,
+                    Arithmetic::Multiply => a * b,
+                    Arithmetic::Divide => a / b,
+                    Arithmetic::Modulo => a % b,
+                })
+            }
+            Expr::Negate(a) => XPathValue::Number(-self.eval(a, focus)?.to_number(self.document)),
+            Expr::Union(a, b) => {
+                let mut nodes = self.nodes(a, focus)?;
+                nodes.extend(self.nodes(b, focus)?);
+                self.sort(&mut nodes);
+                XPathValue::Nodes(nodes)
+            }
+            Expr::Path(start, steps) => {
+                let mut nodes = match start {
+                    Start::Context => vec![focus.node.clone()],
+                    Start::Root => vec![XPathNode::Document],
+                    Start::Expr(expr) => self.nodes(expr, focus)?,
+                };
+                for step in steps {
+                    nodes = self.step(step, &nodes)?;
+                }
+                XPathValue::Nodes(nodes)
+            }
+            Expr::Filter(expr, predicates) => {
+                let mut nodes = self.nodes(expr, focus)?;
+                for predicate in predicates {
+                    nodes = self.filter(nodes, predicate)?;
+                }
+                XPathValue::Nodes(nodes)
+            }
+            Expr::Literal(s) => XPathValue::String(s.clone()),
+            Expr::Number(n) => XPathValue::Number(*n),
+            Expr::Variable(name) => match self.variables.get(name) {
+                Some(XPathValue::Nodes(nodes)) => {
+                    let mut nodes = nodes.clone();
+                    self.sort(&mut nodes);
+                    XPathValue::Nodes(nodes)
+                }
+                Some(value) => value.clone(),
+                None => return Err(XPathError::UnknownVariable(name.clone())),
+            },
+            Expr::Call(function, args) => self.call(*function, args, focus)?,
+        })
+    }
+
+    fn nodes(&self, expr: &Expr, focus: &Focus) -> Result<Vec<XPathNode>, XPathError> {
+        match self.eval(expr, focus)? {
+            XPathValue::Nodes(nodes) => Ok(nodes),
+            _ => Err(XPathError::NotANodeSet),
+        }
+    }
+
+    fn string(&self, expr: &Expr, focus: &Focus) -> Result<String, XPathError> {
+        Ok(self.eval(expr, focus)?.to_string_value(self.document))
+    }
+
+    fn number(&self, expr: &Expr, focus: &Focus) -> Result<f64, XPathError> {
+        Ok(self.eval(expr, focus)?.to_number(self.document))
+    }
+
+    fn step(&self, step: &Step, input: &[XPathNode]) -> Result<Vec<XPathNode>, XPathError> {
+        let mut output = vec![];
+        for node in input {
+            let mut candidates = self.axis(step.axis, node);
+            candidates.retain(|c| self.test(step, c));
+            for predicate in &step.predicates {
+                candidates = self.filter(candidates, predicate)?;
+            }
+            output.extend(candidates);
+        }
+        if input.len() > 1 || step.axis.is_reverse() {
+            self.sort(&mut output);
+        }
+        Ok(output)
+    }
+
+    /// Keeps the nodes that satisfy `predicate`, with positions counted in the order given.
+    fn filter(
+        &self,
+        nodes: Vec<XPathNode>,
+        predicate: &Expr,
+    ) -> Result<Vec<XPathNode>, XPathError> {
+        if let Expr::Number(n) = predicate {
+            let index = (n.fract() == 0.0 && *n >= 1.0).then(|| *n as usize - 1);
+            return Ok(index
+                .and_then(|i| nodes.into_iter().nth(i))
+                .into_iter()
+                .collect());
+        }
+
+        let size = nodes.len();
+        let mut kept = vec![];
+        for (i, node) in nodes.into_iter().enumerate() {
+            let focus = Focus {
+                node,
+                position: i + 1,
+                size,
+            };
+            let keep = match self.eval(predicate, &focus)? {
+                XPathValue::Number(n) => n == focus.position as f64,
+                value => value.to_boolean(),
+            };
+            if keep {
+                kept.push(focus.node);
+            }
+        }
+        Ok(kept)
+    }
+
+    fn parent(&self, node: &XPathNode) -> Option<XPathNode> {
+        match node {
+            XPathNode::Document => None,
+            XPathNode::Node(n) => match n.parent(self.document) {
+                Some(parent) => Some(parent.into()),
+                None => n
+                    .index_in_parent(self.document)
+                    .map(|_| XPathNode::Document),
+            },
+            XPathNode::Attribute(e, _) => Some((*e).into()),
+        }
+    }
+
+    /// The nodes at the top level of the document, which XPath does not include the document
+    /// type declaration in.
+    fn top_level(&self) -> impl Iterator<Item = Node> + '_ {
+        let document = self.document;
+        document
+            .before
+            .iter()
+            .copied()
+            .chain(iter::once(document.root().as_node()))
+            .chain(document.after.iter().copied())
+            .filter(|n| !matches!(n, Node::DocumentType(_)))
+    }
+
+    /// A node followed by its descendants, in document order.
+    fn subtree(&self, node: Node) -> impl Iterator<Item = XPathNode> + '_ {
+        iter::once(node)
+            .chain(node.descendant_nodes(self.document))
+            .map(XPathNode::Node)
+    }
+
+    /// The nodes on `axis` from `node`, in the order of the axis.
+    fn axis(&self, axis: Axis, node: &XPathNode) -> Vec<XPathNode> {
+        let document = self.document;
+        match axis {
+            Axis::Itself => vec![node.clone()],
+            Axis::Parent => self.parent(node).into_iter().collect(),
+            Axis::Ancestor | Axis::AncestorOrSelf => {
+                let start = match axis {
+                    Axis::Ancestor => self.parent(node),
+                    _ => Some(node.clone()),
+                };
+                iter::successors(start, |n| self.parent(n)).collect()
+            }
+            Axis::Child => match node {
+                XPathNode::Document => self.top_level().map(XPathNode::Node).collect(),
+                XPathNode::Node(Node::Element(e)) => e
+                    .child_nodes(document)
+                    .iter()
+                    .map(|n| XPathNode::Node(*n))
+                    .collect(),
+                _ => vec![],
+            },
+            Axis::Descendant | Axis::DescendantOrSelf => {
+                let mut nodes = match axis {
+                    Axis::Descendant => vec![],
+                    _ => vec![node.clone()],
+                };
+                match node {
+                    XPathNode::Document => {
+                        nodes.extend(self.top_level().flat_map(|n| self.subtree(n)));
+                    }
+                    XPathNode::Node(n) => {
+                        nodes.extend(n.descendant_nodes(document).map(XPathNode::Node));
+                    }
+                    XPathNode::Attribute(..) => {}
+                }
+                nodes
+            }
+            Axis::FollowingSibling | Axis::PrecedingSibling => {
+                let node = match node {
+                    XPathNode::Node(n) => *n,
+                    _ => return vec![],
+                };
+                let siblings: Box<dyn Iterator<Item = Node>> = match axis {
+                    Axis::FollowingSibling => Box::new(node.following_siblings(document)),
+                    _ => Box::new(node.preceding_siblings(document)),
+                };
+                siblings
+                    .filter(|n| !matches!(n, Node::DocumentType(_)))
+                    .map(XPathNode::Node)
+                    .collect()
+            }
+            Axis::Following => {
+                let mut nodes = vec![];
+                let start = match node {
+                    XPathNode::Document => return nodes,
+                    XPathNode::Node(n) => *n,
+                    XPathNode::Attribute(e, _) => {
+                        nodes.extend(e.descendant_nodes(document).map(XPathNode::Node));
+                        e.as_node()
+                    }
+                };
+                for ancestor in iter::once(start).chain(start.ancestors(document).map(Node::from)) {
+                    for sibling in ancestor.following_siblings(document) {
+                        if !matches!(sibling, Node::DocumentType(_)) {
+                            nodes.extend(self.subtree(sibling));
+                        }
+                    }
+                }
+                nodes
+            }
+            Axis::Preceding => {
+                let mut nodes = vec![];
+                let start = match node {
+                    XPathNode::Document => return nodes,
+                    XPathNode::Node(n) => *n,
+                    XPathNode::Attribute(e, _) => e.as_node(),
+                };
+                for ancestor in iter::once(start).chain(start.ancestors(document).map(Node::from)) {
+                    for sibling in ancestor.preceding_siblings(document) {
+                        if !matches!(sibling, Node::DocumentType(_)) {
+                            let subtree = self.subtree(sibling).collect::<Vec<_>>();
+                            nodes.extend(subtree.into_iter().rev());
+                        }
+                    }
+                }
+                nodes
+            }
+            Axis::Attribute => match node {
+                XPathNode::Node(Node::Element(e)) => e
+                    .attributes(document)
+                    .keys()
+                    .filter(|name| !is_namespace_declaration(name))
+                    .map(|name| XPathNode::Attribute(*e, name.clone()))
+                    .collect(),
+                _ => vec![],
+            },
+            Axis::Namespace => vec![],
+        }
+    }
+
+    fn test(&self, step: &Step, node: &XPathNode) -> bool {
+        let document = self.document;
+        let (owner, name, is_attribute) = match (node, step.axis) {
+            (XPathNode::Attribute(e, name), Axis::Attribute) => (*e, name, true),
+            (XPathNode::Node(Node::Element(e)), axis) if axis != Axis::Attribute => {
+                (*e, e.qname(document), false)
+            }
+            _ => {
+                return match step.test {
+                    NodeTest::Node => true,
+                    NodeTest::Text => {
+                        matches!(node, XPathNode::Node(Node::Text(_) | Node::CDataSection(_)))
+                    }
+                    NodeTest::Comment => matches!(node, XPathNode::Node(Node::Comment(_))),
+                    NodeTest::ProcessingInstruction => {
+                        matches!(node, XPathNode::Node(Node::ProcessingInstruction(_)))
+                    }
+                    _ => false,
+                };
+            }
+        };
+
+        let namespace = || match (name.namespace(), is_attribute) {
+            (None, true) => Some(None),
+            (prefix, _) => lookup_namespace(document, owner, prefix),
+        };
+        match &step.test {
+            NodeTest::Node | NodeTest::Any => true,
+            NodeTest::Namespace(uri) => namespace() == Some(Some(uri)),
+            NodeTest::Name {
+                namespace: uri,
+                local,
+            } => name.local_part() == local && namespace() == Some(uri.as_deref()),
+            _ => false,
+        }
+    }
+
+    /// Puts nodes in document order and removes duplicates.
+    fn sort(&self, nodes: &mut Vec<XPathNode>) {
+        nodes.sort_by_cached_key(|n| self.order_key(n));
+        nodes.dedup();
+    }
+
+    /// The positions leading from the document to a node, which compare in document order.
+    /// Attributes come after their element and before its children.
+    fn order_key(&self, node: &XPathNode) -> Vec<(bool, usize)> {
+        let document = self.document;
+        let (mut current, attribute) = match node {
+            XPathNode::Document => return vec![],
+            XPathNode::Node(n) => (*n, None),
+            XPathNode::Attribute(e, name) => {
+                let index = e.attributes(document).get_index_of(name);
+                (e.as_node(), Some((false, index.unwrap_or(usize::MAX))))
+            }
+        };
+
+        let mut key = vec![];
+        while let Some(index) = current.index_in_parent(document) {
+            key.push((true, index));
+            match current.parent(document) {
+                Some(parent) => current = parent.as_node(),
+                None => break,
+            }
+        }
+        key.reverse();
+        key.extend(attribute);
+        key
+    }
+
+    fn compare(&self, op: Comparison, a: XPathValue, b: XPathValue) -> bool {
+        let document = self.document;
+        let strings = |nodes: &[XPathNode]| {
+            nodes
+                .iter()
+                .map(|n| XPathValue::String(n.string_value(document)))
+                .collect::<Vec<_>>()
+        };
+        match (a, b) {
+            (XPathValue::Nodes(a), XPathValue::Nodes(b)) => {
+                let b = strings(&b);
+                strings(&a)
+                    .iter()
+                    .any(|a| b.iter().any(|b| self.compare_values(op, a, b)))
+            }
+            (XPathValue::Nodes(a), b @ XPathValue::Boolean(_)) => {
+                self.compare_values(op, &XPathValue::Boolean(!a.is_empty()), &b)
+            }
+            (a @ XPathValue::Boolean(_), XPathValue::Nodes(b)) => {
+                self.compare_values(op, &a, &XPathValue::Boolean(!b.is_empty()))
+            }
+            (XPathValue::Nodes(a), b) => strings(&a).iter().any(|a| self.compare_values(op, a, &b)),
+            (a, XPathValue::Nodes(b)) => strings(&b).iter().any(|b| self.compare_values(op, &a, b)),
+            (a, b) => self.compare_values(op, &a, &b),
+        }
+    }
+
+    /// Compares two values that are not node-sets.
+    fn compare_values(&self, op: Comparison, a: &XPathValue, b: &XPathValue) -> bool {
+        let number = |v: &XPathValue| v.to_number(self.document);
+        let equal = || match (a, b) {
+            (XPathValue::Boolean(_), _) | (_, XPathValue::Boolean(_)) => {
+                a.to_boolean() == b.to_boolean()
+            }
+            (XPathValue::Number(_), _) | (_, XPathValue::Number(_)) => number(a) == number(b),
+            _ => a.to_string_value(self.document) == b.to_string_value(self.document),
+        };
+        match op {
+            Comparison::Eq => equal(),
+            Comparison::Ne => !equal(),
+            Comparison::Lt => number(a) < number(b),
+            Comparison::Le => number(a) <= number(b),
+            Comparison::Gt => number(a) > number(b),
+            Comparison::Ge => number(a) >= number(b),
+        }
+    }
+
+    /// The first node of the argument, or the context node if there is none.
+    fn node_argument(&self, args: &[Expr], focus: &Focus) -> Result<Option<XPathNode>, XPathError> {
+        match args.first() {
+            Some(arg) => Ok(self.nodes(arg, focus)?.into_iter().next()),
+            None => Ok(Some(focus.node.clone())),
+        }
+    }
+
+    fn string_argument(&self, args: &[Expr], focus: &Focus) -> Result<String, XPathError> {
+        match args.first() {
+            Some(arg) => self.string(arg, focus),
+            None => Ok(focus.node.string_value(self.document)),
+        }
+    }
+
+    fn call(
+        &self,
+        function: Function,
+        args: &[Expr],
+        focus: &Focus,
+    ) -> Result<XPathValue, XPathError> {
+        let document = self.document;
+        let string = |i: usize| self.string(&args[i], focus);
+        let number = |i: usize| self.number(&args[i], focus);
+
+        Ok(match function {
+            Function::Last => XPathValue::Number(focus.size as f64),
+            Function::Position => XPathValue::Number(focus.position as f64),
+            Function::Count => XPathValue::Number(self.nodes(&args[0], focus)?.len() as f64),
+            Function::Id => {
+                let ids = match self.eval(&args[0], focus)? {
+                    XPathValue::Nodes(nodes) => nodes
+                        .iter()
+                        .map(|n| n.string_value(document))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    value => value.to_string_value(document),
+                };
+                let ids = ids.split(is_xml_space).collect::<Vec<_>>();
+                let root = document.root();
+                let nodes = iter::once(root)
+                    .chain(root.descendants(document))
+                    .filter(|e| {
+                        let id = e
+                            .attribute(document, "xml:id")
+                            .or_else(|| e.attribute(document, "id"));
+                        id.is_some_and(|id| ids.contains(&id))
+                    })
+                    .map(XPathNode::from)
+                    .collect();
+                XPathValue::Nodes(nodes)
+            }
+            Function::LocalName | Function::NamespaceUri | Function::Name => {
+                let (owner, name, is_attribute) = match self.node_argument(args, focus)? {
+                    Some(XPathNode::Node(Node::Element(e))) => {
+                        (e, e.qname(document).clone(), false)
+                    }
+                    Some(XPathNode::Attribute(e, name)) => (e, name, true),
+                    _ => return Ok(XPathValue::String(String::new())),
+                };
+                XPathValue::String(match function {
+                    Function::LocalName => name.local_part().to_string(),
+                    Function::Name => name.prefixed_name().to_string(),
+                    _ => match (name.namespace(), is_attribute) {
+                        (None, true) => String::new(),
+                        (prefix, _) => lookup_namespace(document, owner, prefix)
+                            .flatten()
+                            .unwrap_or_default()
+                            .to_string(),
+                    },
+                })
+            }
+            Function::String => XPathValue::String(self.string_argument(args, focus)?),
+            Function::Concat => XPathValue::String(
+                args.iter()
+                    .map(|arg| self.string(arg, focus))
+                    .collect::<Result<String, _>>()?,
+            ),
+            Function::StartsWith => XPathValue::Boolean(string(0)?.starts_with(&string(1)?)),
+            Function::Contains => XPathValue::Boolean(string(0)?.contains(&string(1)?)),
+            Function::SubstringBefore => {
+                let (s, pattern) = (string(0)?, string(1)?);
+                XPathValue::String(match s.find(&pattern) {
+                    Some(i) => s[..i].to_string(),
+                    None => String::new(),
+                })
+            }
+            Function::SubstringAfter => {
+                let (s, pattern) = (string(0)?, string(1)?);
+                XPathValue::String(match s.find(&pattern) {
+                    Some(i) => s[i + pattern.len()..].to_string(),
+                    None => String::new(),
+                })
+            }
+            Function::Substring => {
+                let s = string(0)?;
+                let start = round(number(1)?);
+                let end = match args.len() {
+                    3 => start + round(number(2)?),
+                    _ => f64::INFINITY,
+                };
+                XPathValue::String(
+                    s.chars()
+                        .enumerate()
+                        .filter(|(i, _)| {
+                            let position = (i + 1) as f64;
+                            position >= start && position < end
+                        })
+                        .map(|(_, c)| c)
+                        .collect(),
+                )
+            }
+            Function::StringLength => {
+                XPathValue::Number(self.string_argument(args, focus)?.chars().count() as f64)
+            }
+            Function::NormalizeSpace => XPathValue::String(
+                self.string_argument(args, focus)?
+                    .split(is_xml_space)
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            Function::Translate => {
+                let (s, from, to) = (string(0)?, string(1)?, string(2)?);
+                let to = to.chars().collect::<Vec<_>>();
+                XPathValue::String(
+                    s.chars()
+                        .filter_map(|c| match from.chars().position(|x| x == c) {
+                            Some(i) => to.get(i).copied(),
+                            None => Some(c),
+                        })
+                        .collect(),
+                )
+            }
+            Function::Boolean => XPathValue::Boolean(self.eval(&args[0], focus)?.to_boolean()),
+            Function::Not => XPathValue::Boolean(!self.eval(&args[0], focus)?.to_boolean()),
+            Function::True => XPathValue::Boolean(true),
+            Function::False => XPathValue::Boolean(false),
+            Function::Lang => {
+                let lang = string(0)?.to_lowercase();
+                let element = match &focus.node {
+                    XPathNode::Document => None,
+                    XPathNode::Node(Node::Element(e)) => Some(*e),
+                    XPathNode::Node(n) => n.parent(document),
+                    XPathNode::Attribute(e, _) => Some(*e),
+                };
+                let declared = element.and_then(|e| {
+                    iter::once(e)
+                        .chain(e.ancestors(document))
+                        .find_map(|e| e.attribute(document, "xml:lang"))
+                });
+                XPathValue::Boolean(declared.is_some_and(|declared| {
+                    let declared = declared.to_lowercase();
+                    declared == lang
+                        || declared
+                            .strip_prefix(&lang)
+                            .is_some_and(|rest| rest.starts_with('-'))
+                }))
+            }
+            Function::Number => XPathValue::Number(match args.first() {
+                Some(arg) => self.number(arg, focus)?,
+                None => parse_number(&focus.node.string_value(document)),
+            }),
+            Function::Sum => XPathValue::Number(
+                self.nodes(&args[0], focus)?
+                    .iter()
+                    .map(|n| parse_number(&n.string_value(document)))
+                    .sum(),
+            ),
+            Function::Floor => XPathValue::Number(number(0)?.floor()),
+            Function::Ceiling => XPathValue::Number(number(0)?.ceil()),
+            Function::Round => XPathValue::Number(round(number(0)?)),
+        })
+    }
+}
+
+/// Rounds half up, as XPath does, rather than away from zero.
+fn round(n: f64) -> f64 {
+    if n.is_nan() || n.is_infinite() || n == 0.0 {
+        return n;
+    }
+    let rounded = (n + 0.5).floor();
+    match rounded == 0.0 && n < 0.0 {
+        true => -0.0,
+        false => rounded,
+    }
+}
+
+fn is_namespace_declaration(name: &QName) -> bool {
+    name.namespace() == Some("xmlns") || name.prefixed_name() == "xmlns"
+}
+
+/// The namespace URI that `prefix` is bound to on `element`, with `None` as the prefix for the
+/// default namespace. Returns `Some(None)` for no namespace, and `None` if the prefix is not
+/// declared at all.
+fn lookup_namespace<'d>(
+    document: &'d Document,
+    element: Element,
+    prefix: Option<&str>,
+) -> Option<Option<&'d str>> {
+    if prefix == Some("xml") {
+        return Some(Some(XML_NAMESPACE));
+    }
+
+    for element in iter::once(element).chain(element.ancestors(document)) {
+        for (name, value) in element.attributes(document) {
+            let declares = match prefix {
+                Some(prefix) => name.namespace() == Some("xmlns") && name.local_part() == prefix,
+                None => name.prefixed_name() == "xmlns",
+            };
+            if declares {
+                return Some((!value.is_empty()).then_some(value.as_str()));
+            }
+        }
+    }
+
+    match prefix {
+        Some(_) => None,
+        None => Some(None),
+    }
+}